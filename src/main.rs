@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{self, AtomicBool, AtomicU64};
 use std::sync::{Arc, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
@@ -13,6 +19,12 @@ pub struct Document {
     pub content: String,
     pub metadata: HashMap<String, String>,
     pub timestamp: u64,
+    /// Numeric attributes for range filters and numeric sorting.
+    #[serde(default)]
+    pub numeric_metadata: HashMap<String, f64>,
+    /// Access-control groups allowed to see this document; empty means public.
+    #[serde(default)]
+    pub acl: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,16 +35,111 @@ pub struct SearchResult {
     pub score: f32,
     pub highlights: Vec<String>,
     pub metadata: HashMap<String, String>,
+    /// Indexed terms, including expansions, that contributed to the score.
+    #[serde(default)]
+    pub matched_terms: Vec<String>,
+    /// Highlight snippets keyed by field.
+    #[serde(default)]
+    pub field_highlights: HashMap<String, Vec<String>>,
+    /// The highlights as one string, with `HighlightOutput::Joined`.
+    #[serde(default)]
+    pub joined_highlight: Option<String>,
+    /// Each matched term's BM25 contribution, with `SearchQuery::debug_scores`.
+    #[serde(default)]
+    pub term_scores: HashMap<String, f32>,
+    /// Lower-confidence match added by `SearchQuery::fallback`.
+    #[serde(default)]
+    pub fallback: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResponse {
     pub results: Vec<SearchResult>,
     pub total_hits: usize,
+    /// Whether `total_hits` is exact or a lower bound (see `track_total_hits`).
+    #[serde(default)]
+    pub total_hits_relation: Relation,
     pub query_time_ms: u64,
     pub page: usize,
     pub per_page: usize,
     pub total_pages: usize,
+    /// Whether a later page exists, i.e. `page < total_pages`.
+    pub has_next: bool,
+    /// Whether an earlier page exists, i.e. `page > 1`.
+    pub has_prev: bool,
+    pub next_page: Option<usize>,
+    /// For an out-of-range page, the last valid page rather than `page - 1`.
+    pub prev_page: Option<usize>,
+    /// Set when `page` lies past `total_pages`; `results` is then empty.
+    #[serde(default)]
+    pub out_of_range: bool,
+    /// Set when a fuzzy or prefix term hit the engine's `max_expansions` cap.
+    pub truncated_expansion: bool,
+    /// `(lower, upper, count)` per score interval when `bucket_scores` is set.
+    pub buckets: Vec<(f32, f32, usize)>,
+    /// The analyzed query terms; empty when analysis dropped every word.
+    pub query_tokens: Vec<String>,
+    /// Scores across all matches, with `include_score_stats`.
+    pub score_stats: Option<ScoreStats>,
+    /// `FerrumSearch::index_generation` when the query ran.
+    #[serde(default)]
+    pub index_generation: u64,
+    /// With `group_by_field`, the page's results keyed by `"title"` or `"content"`.
+    #[serde(default)]
+    pub field_groups: HashMap<String, Vec<SearchResult>>,
+    /// Set when `SearchQuery::cancellation` stopped the search.
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScoreStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    /// Population standard deviation.
+    pub std_dev: f32,
+}
+
+impl ScoreStats {
+    fn from_scores(scores: &[f32]) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+        let count = scores.len() as f32;
+        let mean = scores.iter().sum::<f32>() / count;
+        let variance = scores.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / count;
+        Some(Self {
+            min: scores.iter().copied().fold(f32::INFINITY, f32::min),
+            max: scores.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+            mean,
+            std_dev: variance.sqrt(),
+        })
+    }
+}
+
+/// How `search_multi` merges a document's scores from several queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CombineMode {
+    Sum,
+    Max,
+}
+
+/// Rough size of a query, from `FerrumSearch::estimate_cost`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryCost {
+    /// Distinct documents in the posting lists of the expanded terms.
+    pub candidate_docs: usize,
+    /// Distinct indexed terms the query expands to, fuzzy and prefix included.
+    pub expanded_terms: usize,
+}
+
+/// How a reported hit count relates to the true number of matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Relation {
+    #[default]
+    Equal,
+    GreaterThanOrEqual,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +150,111 @@ pub struct IndexStats {
     pub version: String,
 }
 
+/// Fragmentation indicators from `FerrumSearch::index_health`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexHealth {
+    /// Document handles freed by removals, until reused or `optimize` compacts them.
+    pub empty_postings: usize,
+    /// Mean number of documents per term.
+    pub avg_posting_len: f64,
+    pub vocabulary_size: usize,
+    /// Share of documents removed or replaced since the last `optimize`.
+    pub deleted_ratio: f64,
+}
+
+/// Counters over the non-silent searches served, from `FerrumSearch::query_metrics`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryMetrics {
+    pub query_count: u64,
+    /// Sum of the reported `query_time_ms`, cache hits included.
+    pub total_time_ms: u64,
+    pub max_time_ms: u64,
+}
+
+/// One logged search, as exported by `FerrumSearch::export_analytics`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryRecord {
+    /// The query text, whitespace-normalized and lowercased.
+    pub query: String,
+    /// Number of analyzed query terms.
+    pub term_count: usize,
+    pub hit_count: usize,
+    pub latency_ms: u64,
+    /// When the search ran, in seconds since the epoch.
+    pub timestamp: u64,
+}
+
+/// Outcome of a streaming import, with the (1-based) lines that failed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BulkImportReport {
+    pub imported: usize,
+    pub errors: Vec<(usize, FerrumError)>,
+}
+
+// ==================== ERRORS ====================
+
+/// Errors returned by the fallible `FerrumSearch` operations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FerrumError {
+    /// The document produced no tokens and the engine rejects empty documents.
+    EmptyDocument(String),
+    /// `insert_document` was given an id that is already indexed.
+    DuplicateId(String),
+    /// No document with this id exists.
+    NotFound(String),
+    /// A field name other than `"title"` or `"content"`.
+    UnknownField(String),
+    /// `page` was zero or `per_page` was outside `1..=MAX_PER_PAGE`.
+    InvalidPagination,
+    /// A malformed query: empty filter keys, contradictory terms, inverted ranges.
+    InvalidQuery(String),
+    /// An argument that can never succeed, such as an empty metadata key.
+    InvalidArgument(String),
+    /// The index swap lock was poisoned by a panic in another thread.
+    LockPoisoned,
+    /// A document or index failed to (de)serialize.
+    Serialization(String),
+    /// Reading or writing a file failed.
+    Io(String),
+    /// A document id broke the engine's `IdPolicy`.
+    InvalidId { id: String, reason: String },
+    /// A document's content exceeded `max_content_bytes` under `OversizedContentPolicy::Reject`.
+    ContentTooLarge { id: String, bytes: usize, limit: usize },
+}
+
+impl std::fmt::Display for FerrumError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FerrumError::EmptyDocument(id) => write!(f, "Document '{}' has no indexable content", id),
+            FerrumError::DuplicateId(id) => write!(f, "Document with id '{}' already exists", id),
+            FerrumError::NotFound(id) => write!(f, "Document '{}' not found", id),
+            FerrumError::UnknownField(field) => {
+                write!(f, "Unknown field '{}'; expected one of {:?}", field, ANALYZED_FIELDS)
+            }
+            FerrumError::InvalidPagination => {
+                write!(f, "page must be at least 1 and per_page between 1 and {}", MAX_PER_PAGE)
+            }
+            FerrumError::InvalidQuery(reason) => write!(f, "Invalid query: {}", reason),
+            FerrumError::InvalidArgument(reason) => write!(f, "{}", reason),
+            FerrumError::LockPoisoned => write!(f, "Index lock poisoned by a panicked thread"),
+            FerrumError::Serialization(reason) => write!(f, "Serialization failed: {}", reason),
+            FerrumError::Io(reason) => write!(f, "I/O error: {}", reason),
+            FerrumError::InvalidId { id, reason } => write!(f, "Invalid document id {:?}: {}", id, reason),
+            FerrumError::ContentTooLarge { id, bytes, limit } => {
+                write!(f, "Document '{}' content is {} bytes, over the {} byte limit", id, bytes, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FerrumError {}
+
+impl<T> From<std::sync::PoisonError<T>> for FerrumError {
+    fn from(_: std::sync::PoisonError<T>) -> Self {
+        FerrumError::LockPoisoned
+    }
+}
+
 // ==================== SEARCH QUERY STRUCTURE ====================
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,615 +265,7835 @@ pub struct SearchQuery {
     pub per_page: Option<usize>,
     pub filters: Option<HashMap<String, String>>,
     pub sort_by: Option<String>,
+    /// `None` uses `SearchDefaults::highlight`.
+    #[serde(default)]
+    pub highlight: Option<bool>,
+    #[serde(default)]
+    pub query_type: QueryType,
+    /// Characters of content returned per result; `None` returns it in full.
+    #[serde(default = "default_result_content_len")]
+    pub result_content_len: Option<usize>,
+    /// Score thresholds splitting all matches into counted buckets.
+    #[serde(default)]
+    pub bucket_scores: Option<Vec<f32>>,
+    /// Minimum number of query terms a document must match to be returned.
+    #[serde(default)]
+    pub minimum_should_match: Option<MinimumShouldMatch>,
+    /// Inclusive ranges over `Document::numeric_metadata`, all of which must hold.
+    #[serde(default)]
+    pub numeric_filters: Option<HashMap<String, NumericRange>>,
+    #[serde(default)]
+    pub highlight_mode: HighlightMode,
+    #[serde(default)]
+    pub highlight_output: HighlightOutput,
+    #[serde(default)]
+    pub highlight_config: HighlightConfig,
+    /// Keep only the first this-many hits, reported as a lower bound. With
+    /// `sort`/`sort_by` or a re-ranker every match is still ordered first.
+    #[serde(default)]
+    pub track_total_hits: Option<usize>,
+    /// Whether every term or any term must match; `None` uses the engine default.
+    #[serde(default)]
+    pub operator: Option<QueryOperator>,
+    /// Fields (`"title"`, `"content"`) to score; `None` uses the engine default.
+    #[serde(default)]
+    pub search_fields: Option<Vec<String>>,
+    /// Metadata key/values a document must not have.
+    #[serde(default)]
+    pub exclude_filters: Option<HashMap<String, String>>,
+    /// Metadata keys a document must have, whatever their value.
+    #[serde(default)]
+    pub has_fields: Option<Vec<String>>,
+    /// Metadata keys a document must not have.
+    #[serde(default)]
+    pub missing_fields: Option<Vec<String>>,
+    /// Summarize the scores of every match in `SearchResponse::score_stats`.
+    #[serde(default)]
+    pub include_score_stats: bool,
+    /// Return only the single `best_snippet` as each result's highlight.
+    #[serde(default)]
+    pub best_snippet_only: bool,
+    /// Multi-key ordering, compared key by key; takes precedence over `sort_by`.
+    #[serde(default)]
+    pub sort: Option<Vec<(String, SortOrder)>>,
+    /// The caller's access-control groups, checked against `Document::acl`.
+    #[serde(default)]
+    pub allowed_acls: Option<Vec<String>>,
+    /// Score factors for documents matching every key/value of a set.
+    #[serde(default)]
+    pub boost_filters: Option<Vec<(HashMap<String, String>, f32)>>,
+    /// Restricts scoring to these document ids.
+    #[serde(default)]
+    pub only_ids: Option<Vec<String>>,
+    /// Hard cap on the ranked results considered for pagination.
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// Bypasses the result cache, the query log, and `query_metrics`.
+    #[serde(default)]
+    pub silent: bool,
+    /// Also match sound-alike terms (see `set_phonetic`).
+    #[serde(default)]
+    pub phonetic: bool,
+    /// Fill in `SearchResult::term_scores`. Not supported by `search_multi`.
+    #[serde(default)]
+    pub debug_scores: bool,
+    /// Relaxed retry for strict queries that match too few documents.
+    #[serde(default)]
+    pub fallback: Option<FallbackStrategy>,
+    /// Also fill `SearchResponse::field_groups`.
+    #[serde(default)]
+    pub group_by_field: bool,
+    /// Once set, the search stops and returns an empty, `cancelled` response.
+    #[serde(skip)]
+    pub cancellation: Option<CancellationToken>,
+    /// Clauses from a `StructuredQuery`, used instead of parsing `query`.
+    #[serde(default)]
+    pub bool_query: Option<BoolQuery>,
+}
+
+/// Shared flag a caller sets to abandon a running search.
+pub type CancellationToken = Arc<AtomicBool>;
+
+fn is_cancelled(token: Option<&CancellationToken>) -> bool {
+    token.is_some_and(|token| token.load(atomic::Ordering::Relaxed))
+}
+
+/// Elasticsearch-style JSON query DSL, e.g.
+/// `{"bool": {"must": ["rust"], "must_not": ["legacy"]}, "filters": {"category": "web"}}`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StructuredQuery {
+    #[serde(default, rename = "bool")]
+    pub bool_query: BoolQuery,
+    #[serde(default)]
+    pub filters: HashMap<String, String>,
+    #[serde(default)]
+    pub exclude_filters: HashMap<String, String>,
+    #[serde(default)]
+    pub sort: Option<Vec<(String, SortOrder)>>,
+    #[serde(default)]
+    pub boosts: Vec<FilterBoost>,
+    #[serde(default)]
+    pub fuzzy: bool,
+    #[serde(default)]
+    pub page: Option<usize>,
+    #[serde(default)]
+    pub per_page: Option<usize>,
+}
+
+/// Text clauses: every `must` word is required, `should` words add score,
+/// and documents containing any `must_not` word are dropped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BoolQuery {
+    #[serde(default)]
+    pub must: Vec<String>,
+    #[serde(default)]
+    pub should: Vec<String>,
+    #[serde(default)]
+    pub must_not: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterBoost {
+    pub filter: HashMap<String, String>,
+    pub factor: f32,
+}
+
+impl From<StructuredQuery> for SearchQuery {
+    fn from(structured: StructuredQuery) -> Self {
+        let clauses = &structured.bool_query;
+        // Display form for logs and analytics; matching reads `bool_query`
+        let words = |clauses: &[String], prefix: &'static str| -> Vec<String> {
+            clauses
+                .iter()
+                .flat_map(|clause| clause.split_whitespace())
+                .map(|word| format!("{}{}", prefix, word))
+                .collect()
+        };
+        let mut terms = words(&clauses.must, "+");
+        terms.extend(words(&clauses.should, ""));
+        terms.extend(words(&clauses.must_not, "-"));
+
+        let non_empty = |map: HashMap<String, String>| (!map.is_empty()).then_some(map);
+        SearchQuery {
+            query: terms.join(" "),
+            fuzzy: structured.fuzzy,
+            page: structured.page.or(Some(1)),
+            per_page: structured.per_page,
+            filters: non_empty(structured.filters),
+            exclude_filters: non_empty(structured.exclude_filters),
+            sort: structured.sort,
+            boost_filters: (!structured.boosts.is_empty()).then(|| {
+                structured.boosts.into_iter().map(|boost| (boost.filter, boost.factor)).collect()
+            }),
+            bool_query: Some(structured.bool_query),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// How the terms of a query combine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum QueryOperator {
+    /// Any term may match; more matching terms score higher.
+    #[default]
+    Or,
+    /// Every term must match.
+    And,
+}
+
+/// Fallbacks for query options a `SearchQuery` leaves unset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchDefaults {
+    pub operator: QueryOperator,
+    /// Used when `per_page` is `None`.
+    pub per_page: usize,
+    /// Used when `SearchQuery::highlight` is `None`.
     pub highlight: bool,
+    /// Used when `search_fields` is `None`; `None` here searches every field.
+    pub search_fields: Option<Vec<String>>,
 }
 
-impl Default for SearchQuery {
+impl Default for SearchDefaults {
     fn default() -> Self {
         Self {
-            query: String::new(),
-            fuzzy: false,
-            page: Some(1),
-            per_page: Some(10),
-            filters: None,
-            sort_by: None,
+            operator: QueryOperator::Or,
+            per_page: 10,
             highlight: true,
+            search_fields: None,
         }
     }
 }
 
-// ==================== SEARCH ENGINE CORE ====================
+/// How highlight snippets are chosen from a matching document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HighlightMode {
+    /// One window around the first occurrence of each query term.
+    #[default]
+    FirstOccurrence,
+    /// Windows around every occurrence, those containing the most distinct
+    /// query terms first.
+    Density,
+    /// The whole sentence around the first occurrence of each query term,
+    /// bounded by `.`, `!`, `?`, newlines, or the title/content boundary.
+    SentenceWindow,
+}
 
-pub struct FerrumSearch {
-    documents: Arc<RwLock<HashMap<String, Document>>>,
-    inverted_index: Arc<RwLock<HashMap<String, Vec<String>>>>,
-    word_frequencies: Arc<RwLock<HashMap<String, HashMap<String, f32>>>>,
-    document_lengths: Arc<RwLock<HashMap<String, usize>>>,
-    total_documents: Arc<RwLock<usize>>,
+/// Shape of the highlight snippets on each result.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HighlightOutput {
+    /// Snippets only as the `highlights` list.
+    #[default]
+    Fragments,
+    /// Also joins the snippets with this separator into `joined_highlight`.
+    Joined(String),
 }
 
-impl FerrumSearch {
-    pub fn new() -> Self {
+/// Snippet budgets for highlighting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighlightConfig {
+    /// Cap on `highlights`, and on every field without its own entry.
+    pub max_snippets: usize,
+    /// Snippet caps for `field_highlights` by field name.
+    #[serde(default)]
+    pub field_max_snippets: HashMap<String, usize>,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
         Self {
-            documents: Arc::new(RwLock::new(HashMap::new())),
-            inverted_index: Arc::new(RwLock::new(HashMap::new())),
-            word_frequencies: Arc::new(RwLock::new(HashMap::new())),
-            document_lengths: Arc::new(RwLock::new(HashMap::new())),
-            total_documents: Arc::new(RwLock::new(0)),
+            max_snippets: 3,
+            field_max_snippets: HashMap::new(),
         }
     }
+}
 
-    // ==================== INDEXING OPERATIONS ====================
+impl HighlightConfig {
+    fn limit_for(&self, field: &str) -> usize {
+        self.field_max_snippets.get(field).copied().unwrap_or(self.max_snippets)
+    }
+}
 
-    pub fn add_document(&self, mut document: Document) -> Result<(), String> {
-        if document.id.is_empty() {
-            document.id = Uuid::new_v4().to_string();
-        }
+/// Re-runs a query matching fewer than `min_results` documents as a relaxed
+/// OR query and appends the new matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FallbackStrategy {
+    pub min_results: usize,
+    #[serde(default)]
+    pub fuzzy: bool,
+}
 
-        let doc_id = document.id.clone();
-        let text = format!("{} {}", document.title, document.content);
-        let tokens = self.tokenize(&text);
-        
-        // Store document
-        {
-            let mut docs = self.documents.write().unwrap();
-            let is_new = !docs.contains_key(&doc_id);
-            docs.insert(doc_id.clone(), document);
-            
-            if is_new {
-                let mut total = self.total_documents.write().unwrap();
-                *total += 1;
-            }
+impl FallbackStrategy {
+    fn relax(&self, query: &SearchQuery) -> SearchQuery {
+        SearchQuery {
+            operator: Some(QueryOperator::Or),
+            minimum_should_match: None,
+            query_type: QueryType::Standard,
+            fuzzy: query.fuzzy || self.fuzzy,
+            fallback: None,
+            ..query.clone()
         }
+    }
+}
 
-        // Update inverted index and frequencies
-        {
-            let mut index = self.inverted_index.write().unwrap();
-            let mut frequencies = self.word_frequencies.write().unwrap();
-            let mut doc_lengths = self.document_lengths.write().unwrap();
+/// Inclusive numeric bounds; a missing bound is open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct NumericRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
 
-            // Remove old entries if updating
-            self.remove_document_from_index(&doc_id, &mut index, &mut frequencies);
+impl NumericRange {
+    /// Ids in a value-sorted posting list that fall inside the range.
+    fn matching<'a>(&self, postings: &'a [(f64, String)]) -> impl Iterator<Item = &'a String> {
+        let start = self.min.map_or(0, |min| postings.partition_point(|(v, _)| *v < min));
+        let end = self.max.map_or(postings.len(), |max| postings.partition_point(|(v, _)| *v <= max));
+        postings[start..end.max(start)].iter().map(|(_, id)| id)
+    }
 
-            // Add new entries
-            let mut word_count = HashMap::new();
-            for token in &tokens {
-                *word_count.entry(token.clone()).or_insert(0) += 1;
-                
-                index.entry(token.clone())
-                    .or_insert_with(Vec::new)
-                    .push(doc_id.clone());
-            }
+    fn contains(&self, value: f64) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
 
-            // Calculate TF scores
-            let doc_length = tokens.len();
-            doc_lengths.insert(doc_id.clone(), doc_length);
-            
-            let mut doc_frequencies = HashMap::new();
-            for (word, count) in word_count {
-                let tf = count as f32 / doc_length as f32;
-                doc_frequencies.insert(word, tf);
-            }
-            frequencies.insert(doc_id, doc_frequencies);
-        }
+/// Either an absolute term count or a percentage of the query's terms
+/// (rounded down). Parses from `"2"` or `"75%"`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MinimumShouldMatch {
+    Count(usize),
+    Percentage(f32),
+}
 
-        Ok(())
+impl MinimumShouldMatch {
+    fn required(&self, term_count: usize) -> usize {
+        let required = match *self {
+            MinimumShouldMatch::Count(count) => count,
+            MinimumShouldMatch::Percentage(pct) => (term_count as f32 * pct / 100.0).floor() as usize,
+        };
+        required.min(term_count)
     }
+}
 
-    pub fn remove_document(&self, doc_id: &str) -> Result<(), String> {
-        {
-            let mut docs = self.documents.write().unwrap();
-            if docs.remove(doc_id).is_some() {
-                let mut total = self.total_documents.write().unwrap();
-                *total = total.saturating_sub(1);
-            }
-        }
+impl std::str::FromStr for MinimumShouldMatch {
+    type Err = FerrumError;
 
-        {
-            let mut index = self.inverted_index.write().unwrap();
-            let mut frequencies = self.word_frequencies.write().unwrap();
-            self.remove_document_from_index(doc_id, &mut index, &mut frequencies);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.strip_suffix('%') {
+            Some(pct) => pct
+                .trim()
+                .parse::<f32>()
+                .map(MinimumShouldMatch::Percentage)
+                .map_err(|e| FerrumError::InvalidArgument(format!("Invalid percentage '{}': {}", s, e))),
+            None => s
+                .parse::<usize>()
+                .map(MinimumShouldMatch::Count)
+                .map_err(|e| FerrumError::InvalidArgument(format!("Invalid term count '{}': {}", s, e))),
         }
-
-        Ok(())
     }
+}
 
-    fn remove_document_from_index(
-        &self,
-        doc_id: &str,
-        index: &mut HashMap<String, Vec<String>>,
-        frequencies: &mut HashMap<String, HashMap<String, f32>>,
-    ) {
-        frequencies.remove(doc_id);
-        
-        // Remove from inverted index
-        let words_to_clean: Vec<String> = index
-            .iter()
-            .filter(|(_, docs)| docs.contains(&doc_id.to_string()))
-            .map(|(word, _)| word.clone())
-            .collect();
+fn default_result_content_len() -> Option<usize> {
+    Some(200)
+}
 
-        for word in words_to_clean {
-            if let Some(docs) = index.get_mut(&word) {
-                docs.retain(|id| id != doc_id);
-                if docs.is_empty() {
-                    index.remove(&word);
-                }
-            }
+/// How the tokens of `SearchQuery::query` are matched against the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum QueryType {
+    /// Independent terms scored with BM25.
+    #[default]
+    Standard,
+    /// All terms must appear consecutively, in order.
+    Phrase,
+    /// Like `Phrase`, but the last term matches by prefix (search-as-you-type).
+    PhrasePrefix,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            fuzzy: false,
+            page: Some(1),
+            per_page: None,
+            filters: None,
+            sort_by: None,
+            highlight: None,
+            query_type: QueryType::Standard,
+            result_content_len: default_result_content_len(),
+            bucket_scores: None,
+            minimum_should_match: None,
+            numeric_filters: None,
+            highlight_mode: HighlightMode::FirstOccurrence,
+            highlight_output: HighlightOutput::Fragments,
+            highlight_config: HighlightConfig::default(),
+            track_total_hits: None,
+            operator: None,
+            search_fields: None,
+            exclude_filters: None,
+            has_fields: None,
+            missing_fields: None,
+            include_score_stats: false,
+            best_snippet_only: false,
+            sort: None,
+            allowed_acls: None,
+            boost_filters: None,
+            only_ids: None,
+            limit: None,
+            silent: false,
+            phonetic: false,
+            debug_scores: false,
+            fallback: None,
+            group_by_field: false,
+            cancellation: None,
+            bool_query: None,
         }
     }
+}
 
-    // ==================== SEARCH OPERATIONS ====================
+// ==================== TEXT ANALYSIS ====================
 
-    pub fn search(&self, query: SearchQuery) -> Result<SearchResponse, String> {
-        let start_time = SystemTime::now();
-        
-        let tokens = self.tokenize(&query.query);
-        if tokens.is_empty() {
-            return Ok(SearchResponse {
-                results: vec![],
-                total_hits: 0,
-                query_time_ms: 0,
-                page: query.page.unwrap_or(1),
-                per_page: query.per_page.unwrap_or(10),
-                total_pages: 0,
-            });
-        }
+/// One step of the analysis pipeline, applied to the output of the base
+/// tokenizer (or of the previous filter).
+pub trait TokenFilter: Send + Sync {
+    fn filter(&self, tokens: Vec<String>) -> Vec<String>;
+}
 
-        let mut scores = HashMap::new();
-        let docs = self.documents.read().unwrap();
-        let index = self.inverted_index.read().unwrap();
-        let frequencies = self.word_frequencies.read().unwrap();
-        let total_docs = *self.total_documents.read().unwrap();
+/// Ordered pipeline of token filters shared by the index and query paths.
+pub struct Analyzer {
+    filters: Vec<Box<dyn TokenFilter>>,
+    kept_symbols: Vec<char>,
+    keep_numbers: bool,
+    cjk: Option<CjkSegmentation>,
+    shingle_size: Option<usize>,
+}
 
-        // Calculate BM25 scores
-        for token in &tokens {
-            let matching_docs = if query.fuzzy {
-                self.fuzzy_search_token(&token, &index)
-            } else {
-                index.get(token).cloned().unwrap_or_default()
-            };
+/// How runs of CJK characters, which aren't whitespace-delimited, are split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CjkSegmentation {
+    /// One token per character.
+    Unigrams,
+    /// One token per overlapping character pair; a lone character stays a unigram.
+    Bigrams,
+}
 
-            let df = matching_docs.len();
-            if df == 0 { continue; }
-
-            let idf = ((total_docs as f32 - df as f32 + 0.5) / (df as f32 + 0.5)).ln();
-
-            for doc_id in matching_docs {
-                if let Some(doc_freqs) = frequencies.get(&doc_id) {
-                    if let Some(&tf) = doc_freqs.get(token) {
-                        let k1 = 1.5;
-                        let b = 0.75;
-                        let doc_len = self.document_lengths.read().unwrap()
-                            .get(&doc_id).copied().unwrap_or(1);
-                        let avg_doc_len = 100.0; // Simplified average
-
-                        let bm25_tf = (tf * (k1 + 1.0)) / 
-                            (tf + k1 * (1.0 - b + b * (doc_len as f32 / avg_doc_len)));
-                        
-                        let score = idf * bm25_tf;
-                        *scores.entry(doc_id.clone()).or_insert(0.0) += score;
-                    }
-                }
-            }
+impl Analyzer {
+    /// An analyzer with no filters: just the base tokenizer.
+    pub fn new() -> Self {
+        Self {
+            filters: Vec::new(),
+            kept_symbols: Vec::new(),
+            keep_numbers: false,
+            cjk: None,
+            shingle_size: None,
         }
+    }
 
-        // Apply filters
-        if let Some(filters) = &query.filters {
-            scores.retain(|doc_id, _| {
-                if let Some(doc) = docs.get(doc_id) {
-                    filters.iter().all(|(key, value)| {
-                        doc.metadata.get(key).map_or(false, |v| v == value)
-                    })
-                } else {
-                    false
-                }
-            });
-        }
+    /// Segments CJK runs inside each word with `mode`.
+    pub fn with_cjk(mut self, mode: CjkSegmentation) -> Self {
+        self.cjk = Some(mode);
+        self
+    }
 
-        // Sort results
-        let mut sorted_results: Vec<_> = scores.into_iter().collect();
-        sorted_results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    /// Keeps these symbols inside tokens, so `c++` or `node.js` stay whole.
+    pub fn with_kept_symbols(mut self, symbols: &str) -> Self {
+        self.kept_symbols.extend(symbols.chars());
+        self
+    }
 
-        let total_hits = sorted_results.len();
-        let page = query.page.unwrap_or(1);
-        let per_page = query.per_page.unwrap_or(10);
-        let total_pages = (total_hits + per_page - 1) / per_page;
+    /// Keeps `.` and `,` between digits, so `3.5kg` or `v2.0` stay whole.
+    pub fn with_number_tokens(mut self) -> Self {
+        self.keep_numbers = true;
+        self
+    }
 
-        // Pagination
-        let start = (page - 1) * per_page;
-        let end = std::cmp::min(start + per_page, total_hits);
-        
-        let mut results = Vec::new();
-        for (doc_id, score) in sorted_results.iter().skip(start).take(end - start) {
-            if let Some(doc) = docs.get(doc_id) {
-                let highlights = if query.highlight {
-                    self.generate_highlights(doc, &tokens)
-                } else {
-                    vec![]
-                };
+    pub fn with_filter(mut self, filter: impl TokenFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
 
-                results.push(SearchResult {
-                    id: doc.id.clone(),
-                    title: doc.title.clone(),
-                    content: self.truncate_content(&doc.content, 200),
-                    score: *score,
-                    highlights,
-                    metadata: doc.metadata.clone(),
-                });
-            }
+    /// Also emits word n-grams of 2 up to `size` adjacent tokens; below 2 disables it.
+    pub fn with_shingles(mut self, size: usize) -> Self {
+        self.shingle_size = (size >= 2).then_some(size);
+        self
+    }
+
+    /// Strips punctuation, splits on whitespace, then runs every filter in order.
+    pub fn analyze(&self, text: &str) -> Vec<String> {
+        let tokens = self
+            .filters
+            .iter()
+            .fold(self.base_tokenize(text), |tokens, filter| filter.filter(tokens));
+        match self.shingle_size {
+            Some(size) => with_shingles(tokens, size),
+            None => tokens,
         }
+    }
 
-        let query_time_ms = start_time.elapsed()
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0);
+    /// Lowercased words as written, before any filter runs.
+    pub fn surface_words(&self, text: &str) -> Vec<String> {
+        self.base_tokenize(text)
+            .into_iter()
+            .map(|word| word.to_lowercase())
+            .collect()
+    }
 
-        Ok(SearchResponse {
-            results,
-            total_hits,
-            query_time_ms,
-            page,
-            per_page,
-            total_pages,
-        })
+    fn base_tokenize(&self, text: &str) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let decimal_separator = |i: usize| {
+            self.keep_numbers
+                && matches!(chars[i], '.' | ',')
+                && i > 0
+                && chars[i - 1].is_ascii_digit()
+                && chars.get(i + 1).is_some_and(char::is_ascii_digit)
+        };
+        chars
+            .iter()
+            .enumerate()
+            .filter(|&(i, c)| {
+                c.is_alphanumeric() || c.is_whitespace() || self.kept_symbols.contains(c) || decimal_separator(i)
+            })
+            .map(|(_, c)| c)
+            .collect::<String>()
+            .split_whitespace()
+            .map(|s| s.trim_end_matches('.'))
+            .filter(|s| s.chars().any(char::is_alphanumeric))
+            .flat_map(|s| match self.cjk {
+                Some(mode) => segment_cjk(s, mode),
+                None => vec![s.to_string()],
+            })
+            .collect()
     }
+}
 
-    // ==================== AUTOCOMPLETE & SUGGESTIONS ====================
+/// Whether an analyzed token is a word n-gram from `Analyzer::with_shingles`.
+fn is_shingle(token: &str) -> bool {
+    token.contains(' ')
+}
 
-    pub fn autocomplete(&self, prefix: &str, limit: usize) -> Vec<String> {
-        let index = self.inverted_index.read().unwrap();
-        let mut suggestions: Vec<_> = index
-            .keys()
-            .filter(|word| word.starts_with(&prefix.to_lowercase()))
-            .take(limit)
-            .cloned()
-            .collect();
-        
-        suggestions.sort();
-        suggestions
+/// BM25 length of a document: its words, not shingles, and at least 1.
+fn indexed_length(tokens: &[String]) -> usize {
+    tokens.iter().filter(|token| !is_shingle(token)).count().max(1)
+}
+
+/// `tokens` followed by every run of 2..=`size` consecutive tokens.
+fn with_shingles(tokens: Vec<String>, size: usize) -> Vec<String> {
+    let mut shingles = Vec::new();
+    for n in 2..=size.min(tokens.len()) {
+        shingles.extend(tokens.windows(n).map(|words| words.join(" ")));
     }
+    let mut all = tokens;
+    all.extend(shingles);
+    all
+}
 
-    pub fn suggest(&self, query: &str) -> Vec<String> {
-        let tokens = self.tokenize(query);
-        let index = self.inverted_index.read().unwrap();
-        
-        let mut suggestions = Vec::new();
-        for token in tokens {
-            let fuzzy_matches = self.fuzzy_search_token(&token, &index);
-            for doc_id in fuzzy_matches.iter().take(3) {
-                if let Some(doc) = self.documents.read().unwrap().get(doc_id) {
-                    suggestions.push(doc.title.clone());
-                }
-            }
-        }
-        
-        suggestions.truncate(5);
-        suggestions
+impl Default for Analyzer {
+    /// Lowercases and drops tokens of two characters or fewer.
+    fn default() -> Self {
+        Self::new().with_filter(Lowercase).with_filter(MinLength(3))
     }
+}
 
-    // ==================== UTILITY METHODS ====================
+pub struct Lowercase;
 
-    fn tokenize(&self, text: &str) -> Vec<String> {
-        text.to_lowercase()
-            .chars()
-            .filter(|c| c.is_alphanumeric() || c.is_whitespace())
-            .collect::<String>()
-            .split_whitespace()
-            .filter(|word| word.len() > 2)
-            .map(|s| s.to_string())
-            .collect()
+impl TokenFilter for Lowercase {
+    fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+        tokens.into_iter().map(|t| t.to_lowercase()).collect()
     }
+}
 
-    fn fuzzy_search_token(&self, token: &str, index: &HashMap<String, Vec<String>>) -> Vec<String> {
-        let mut matches = Vec::new();
-        
-        // Exact match first
-        if let Some(docs) = index.get(token) {
-            matches.extend_from_slice(docs);
-        }
+/// Drops tokens shorter than the given number of bytes.
+pub struct MinLength(pub usize);
 
-        // Fuzzy matches (edit distance = 1)
-        for word in index.keys() {
-            if word != token && self.edit_distance(token, word) <= 1 {
-                if let Some(docs) = index.get(word) {
-                    matches.extend_from_slice(docs);
-                }
-            }
+impl TokenFilter for MinLength {
+    fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+        tokens.into_iter().filter(|t| t.len() >= self.0).collect()
+    }
+}
+
+pub struct StopWords {
+    words: HashSet<String>,
+}
+
+impl StopWords {
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            words: words.into_iter().map(Into::into).collect(),
         }
+    }
 
-        matches.sort();
-        matches.dedup();
-        matches
+    pub fn english() -> Self {
+        Self::new([
+            "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "is", "it",
+            "of", "on", "or", "that", "the", "this", "to", "was", "with",
+        ])
     }
+}
 
-    fn edit_distance(&self, a: &str, b: &str) -> usize {
-        let a_chars: Vec<char> = a.chars().collect();
-        let b_chars: Vec<char> = b.chars().collect();
-        let mut dp = vec![vec![0; b_chars.len() + 1]; a_chars.len() + 1];
+impl TokenFilter for StopWords {
+    fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+        tokens.into_iter().filter(|t| !self.words.contains(t)).collect()
+    }
+}
 
-        for i in 0..=a_chars.len() {
-            dp[i][0] = i;
-        }
-        for j in 0..=b_chars.len() {
-            dp[0][j] = j;
-        }
+pub struct Stem;
 
-        for i in 1..=a_chars.len() {
-            for j in 1..=b_chars.len() {
-                let cost = if a_chars[i-1] == b_chars[j-1] { 0 } else { 1 };
-                dp[i][j] = std::cmp::min(
-                    std::cmp::min(dp[i-1][j] + 1, dp[i][j-1] + 1),
-                    dp[i-1][j-1] + cost
-                );
-            }
-        }
+impl TokenFilter for Stem {
+    fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+        tokens.iter().map(|t| stem(t)).collect()
+    }
+}
 
-        dp[a_chars.len()][b_chars.len()]
+/// Emits each token followed by its configured synonyms.
+pub struct Synonym {
+    synonyms: HashMap<String, Vec<String>>,
+}
+
+impl Synonym {
+    pub fn new(synonyms: HashMap<String, Vec<String>>) -> Self {
+        Self { synonyms }
     }
+}
 
-    fn generate_highlights(&self, doc: &Document, tokens: &[String]) -> Vec<String> {
-        let full_text = format!("{} {}", doc.title, doc.content);
-        let mut highlights = Vec::new();
-        
+impl TokenFilter for Synonym {
+    fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+        let mut expanded = Vec::with_capacity(tokens.len());
         for token in tokens {
-            if let Some(start) = full_text.to_lowercase().find(&token.to_lowercase()) {
-                let context_start = start.saturating_sub(50);
-                let context_end = std::cmp::min(start + token.len() + 50, full_text.len());
-                
-                let mut highlight = full_text[context_start..context_end].to_string();
-                if context_start > 0 {
-                    highlight = format!("...{}", highlight);
-                }
-                if context_end < full_text.len() {
-                    highlight = format!("{}...", highlight);
-                }
-                
-                highlights.push(highlight);
-            }
+            let synonyms = self.synonyms.get(&token).cloned().unwrap_or_default();
+            expanded.push(token);
+            expanded.extend(synonyms);
         }
-        
-        highlights.truncate(3);
-        highlights
+        expanded
     }
+}
 
-    fn truncate_content(&self, content: &str, max_len: usize) -> String {
-        if content.len() <= max_len {
-            content.to_string()
-        } else {
-            format!("{}...", &content[..max_len])
-        }
+/// Folds accented Latin letters to their ASCII base ("café" -> "cafe").
+pub struct DiacriticFold;
+
+impl TokenFilter for DiacriticFold {
+    fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+        tokens
+            .into_iter()
+            .map(|t| t.chars().map(fold_diacritic).collect())
+            .collect()
     }
+}
 
-    // ==================== STATS & MONITORING ====================
+fn fold_diacritic(c: char) -> char {
+    let folded = match c.to_lowercase().next().unwrap_or(c) {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => 'a',
+        'ç' => 'c',
+        'è' | 'é' | 'ê' | 'ë' => 'e',
+        'ì' | 'í' | 'î' | 'ï' => 'i',
+        'ñ' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' => 'o',
+        'ù' | 'ú' | 'û' | 'ü' => 'u',
+        'ý' | 'ÿ' => 'y',
+        _ => return c,
+    };
+    if c.is_uppercase() {
+        folded.to_ascii_uppercase()
+    } else {
+        folded
+    }
+}
 
-    pub fn get_stats(&self) -> IndexStats {
-        let total_docs = *self.total_documents.read().unwrap();
-        let estimated_size = total_docs * 1024; // Rough estimation
-        
-        IndexStats {
-            total_documents: total_docs,
-            index_size_mb: estimated_size as f64 / 1024.0 / 1024.0,
-            last_updated: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
-            version: "1.0.0".to_string(),
-        }
+/// Light suffix-stripping stemmer for common English inflections
+/// ("running" -> "run", "cats" -> "cat", "studies" -> "study").
+fn stem(word: &str) -> String {
+    if !word.is_ascii() || word.len() <= 3 {
+        return word.to_string();
     }
 
-    pub fn bulk_import(&self, documents: Vec<Document>) -> Result<usize, String> {
-        let mut success_count = 0;
-        
-        for doc in documents {
-            match self.add_document(doc) {
-                Ok(_) => success_count += 1,
-                Err(e) => eprintln!("Failed to import document: {}", e),
+    if let Some(base) = word.strip_suffix("ies") {
+        if base.len() >= 2 {
+            return format!("{}y", base);
+        }
+    }
+    if let Some(base) = word.strip_suffix("sses") {
+        return format!("{}ss", base);
+    }
+    for suffix in ["ing", "ed"] {
+        if let Some(base) = word.strip_suffix(suffix) {
+            if base.len() >= 3 {
+                return undouble(base);
             }
         }
-        
-        Ok(success_count)
+    }
+    if let Some(base) = word.strip_suffix('s') {
+        if !base.ends_with('s') && !base.ends_with('u') {
+            return base.to_string();
+        }
     }
 
-    pub fn clear_index(&self) -> Result<(), String> {
-        *self.documents.write().unwrap() = HashMap::new();
-        *self.inverted_index.write().unwrap() = HashMap::new();
-        *self.word_frequencies.write().unwrap() = HashMap::new();
-        *self.document_lengths.write().unwrap() = HashMap::new();
-        *self.total_documents.write().unwrap() = 0;
-        Ok(())
+    word.to_string()
+}
+
+/// "runn" -> "run", keeping legitimate doubles such as "fall" or "pass".
+fn undouble(base: &str) -> String {
+    let bytes = base.as_bytes();
+    let n = bytes.len();
+    if n >= 2 && bytes[n - 1] == bytes[n - 2] && !b"lsz".contains(&bytes[n - 1]) {
+        base[..n - 1].to_string()
+    } else {
+        base.to_string()
     }
 }
 
-// ==================== DEMO & TESTING ====================
+/// Query text split into the words to match and the `-word` exclusions.
+struct ParsedQuery {
+    text: String,
+    excluded: Vec<String>,
+    /// `+word` terms, which also stay in `text` so they contribute score.
+    required: Vec<String>,
+}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// What one search shard's scoring pass found, keyed by document id.
+#[derive(Default)]
+struct ShardScores {
+    scores: HashMap<String, f32>,
+    matched_groups: HashMap<String, HashSet<usize>>,
+    matched_terms: HashMap<String, Vec<String>>,
+    term_scores: HashMap<String, HashMap<String, f32>>,
+}
 
-    #[test]
-    fn test_basic_search() {
-        let engine = FerrumSearch::new();
-        
-        let doc1 = Document {
-            id: "1".to_string(),
-            title: "Rust Programming".to_string(),
-            content: "Rust is a systems programming language focused on safety and performance".to_string(),
-            metadata: HashMap::new(),
-            timestamp: 0,
-        };
+/// Every match of a query in final order, before pagination.
+#[derive(Default)]
+struct RankedMatches {
+    scored: Vec<(String, f32)>,
+    matched_terms: HashMap<String, Vec<String>>,
+    /// Per-document score of each matched term, with `debug_scores`.
+    term_scores: HashMap<String, HashMap<String, f32>>,
+    tokens: Vec<String>,
+    truncated_expansion: bool,
+    /// Matches appended by `SearchQuery::fallback`.
+    fallback_ids: HashSet<String>,
+    cancelled: bool,
+    /// Set when matches past `SearchQuery::track_total_hits` were dropped.
+    hits_capped: bool,
+}
 
-        let doc2 = Document {
-            id: "2".to_string(),
-            title: "Web Development".to_string(),
-            content: "Building web applications with modern frameworks and tools".to_string(),
-            metadata: HashMap::new(),
-            timestamp: 0,
-        };
+/// A scored match ordered best-first; a max-heap keeps the worst on top.
+struct RankedHit(String, f32);
 
-        engine.add_document(doc1).unwrap();
-        engine.add_document(doc2).unwrap();
+impl PartialEq for RankedHit {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
 
-        let query = SearchQuery {
-            query: "rust programming".to_string(),
-            ..Default::default()
-        };
+impl Eq for RankedHit {}
 
-        let results = engine.search(query).unwrap();
-        assert_eq!(results.total_hits, 1);
-        assert_eq!(results.results[0].id, "1");
+impl PartialOrd for RankedHit {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    #[test]
-    fn test_fuzzy_search() {
-        let engine = FerrumSearch::new();
-        
-        let doc = Document {
-            id: "1".to_string(),
-            title: "Programming".to_string(),
-            content: "Advanced programming concepts".to_string(),
-            metadata: HashMap::new(),
-            timestamp: 0,
-        };
+impl Ord for RankedHit {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        FerrumSearch::score_order(self.1, other.1).then_with(|| self.0.cmp(&other.0))
+    }
+}
 
-        engine.add_document(doc).unwrap();
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'      // Hiragana, Katakana
+        | '\u{3400}'..='\u{4DBF}'    // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}'    // CJK Unified Ideographs
+        | '\u{AC00}'..='\u{D7AF}'    // Hangul syllables
+        | '\u{F900}'..='\u{FAFF}'    // CJK Compatibility Ideographs
+        | '\u{20000}'..='\u{2A6DF}') // CJK Extension B
+}
 
-        let query = SearchQuery {
-            query: "programing".to_string(), // Typo
-            fuzzy: true,
-            ..Default::default()
-        };
+/// Splits `word` into its non-CJK stretches, kept whole, and the unigrams or
+/// bigrams of its CJK runs.
+fn segment_cjk(word: &str, mode: CjkSegmentation) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let cjk = is_cjk(chars[start]);
+        let end = chars[start..]
+            .iter()
+            .position(|&c| is_cjk(c) != cjk)
+            .map_or(chars.len(), |len| start + len);
+        let run = &chars[start..end];
+        match (cjk, mode) {
+            (false, _) => tokens.push(run.iter().collect()),
+            (true, CjkSegmentation::Bigrams) if run.len() > 1 => {
+                tokens.extend(run.windows(2).map(|pair| pair.iter().collect::<String>()));
+            }
+            (true, _) => tokens.extend(run.iter().map(|c| c.to_string())),
+        }
+        start = end;
+    }
+    tokens
+}
 
-        let results = engine.search(query).unwrap();
-        assert_eq!(results.total_hits, 1);
+/// Current time in whole seconds since the Unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Escapes text for embedding in HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
     }
+    escaped
 }
 
-fn main() {
-    println!("🔍 FerrumSearch - High-Performance Search Engine");
-    println!("================================================");
-    
-    let engine = FerrumSearch::new();
-    
-    // Demo data
-    let demo_docs = vec![
-        Document {
-            id: "rust-guide".to_string(),
-            title: "The Rust Programming Language Guide".to_string(),
-            content: "Rust is a systems programming language that runs blazingly fast, prevents segfaults, and guarantees thread safety. It accomplishes these goals by being memory safe without using garbage collection.".to_string(),
-            metadata: {
-                let mut meta = HashMap::new();
-                meta.insert("category".to_string(), "programming".to_string());
-                meta.insert("difficulty".to_string(), "intermediate".to_string());
-                meta
-            },
-            timestamp: 1640995200,
-        },
-        Document {
-            id: "web-dev-trends".to_string(),
-            title: "Modern Web Development Trends 2024".to_string(),
-            content: "Web development continues to evolve with new frameworks, tools, and best practices. React, Vue, and Angular dominate the frontend landscape while Node.js powers many backend applications.".to_string(),
-            metadata: {
-                let mut meta = HashMap::new();
-                meta.insert("category".to_string(), "web".to_string());
-                meta.insert("year".to_string(), "2024".to_string());
-                meta
-            },
-            timestamp: 1704067200,
-        },
-        Document {
-            id: "search-algorithms".to_string(),
-            title: "Understanding Search Algorithms".to_string(),
-            content: "Search algorithms are fundamental to computer science. From simple linear search to complex full-text search engines, understanding how search works is crucial for building efficient applications.".to_string(),
-            metadata: {
-                let mut meta = HashMap::new();
-                meta.insert("category".to_string(), "algorithms".to_string());
-                meta.insert("difficulty".to_string(), "advanced".to_string());
-                meta
-            },
-            timestamp: 1672531200,
-        },
-    ];
+/// FNV-1a over title and content, stable across processes.
+fn content_hash_id(document: &Document) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let bytes = document.title.bytes().chain([0]).chain(document.content.bytes());
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
 
-    // Import demo data
-    match engine.bulk_import(demo_docs) {
-        Ok(count) => println!("✅ Successfully imported {} documents", count),
-        Err(e) => println!("❌ Import failed: {}", e),
+// ==================== PHONETIC ENCODING ====================
+
+/// Sound-alike encoding used by the phonetic index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PhoneticAlgorithm {
+    /// American Soundex: first letter plus three consonant-class digits.
+    Soundex,
+    /// Original Metaphone: a consonant skeleton following English spelling rules.
+    Metaphone,
+}
+
+impl PhoneticAlgorithm {
+    /// The code for `term`, from its ASCII letters; `None` if it has none.
+    fn encode(&self, term: &str) -> Option<String> {
+        let letters: Vec<char> = term
+            .chars()
+            .filter(char::is_ascii_alphabetic)
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        if letters.is_empty() {
+            return None;
+        }
+        Some(match self {
+            PhoneticAlgorithm::Soundex => soundex(&letters),
+            PhoneticAlgorithm::Metaphone => metaphone(&letters),
+        })
     }
+}
 
-    // Demo searches
-    println!("\n🔍 Demo Searches:");
-    println!("=================");
+fn soundex(letters: &[char]) -> String {
+    fn digit(c: char) -> Option<char> {
+        match c {
+            'B' | 'F' | 'P' | 'V' => Some('1'),
+            'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => Some('2'),
+            'D' | 'T' => Some('3'),
+            'L' => Some('4'),
+            'M' | 'N' => Some('5'),
+            'R' => Some('6'),
+            _ => None,
+        }
+    }
 
-    // Basic search
-    let query = SearchQuery {
-        query: "rust programming".to_string(),
-        ..Default::default()
-    };
-    
-    match engine.search(query) {
-        Ok(response) => {
-            println!("\n📊 Query: 'rust programming' ({}ms)", response.query_time_ms);
-            println!("   Results: {}/{}", response.results.len(), response.total_hits);
-            for result in &response.results {
-                println!("   • {} (score: {:.2})", result.title, result.score);
+    let mut code = letters[0].to_string();
+    let mut last = digit(letters[0]);
+    for &c in &letters[1..] {
+        let current = digit(c);
+        if current.is_some() && current != last {
+            code.extend(current);
+            if code.len() == 4 {
+                break;
             }
-        },
-        Err(e) => println!("❌ Search failed: {}", e),
+        }
+        // H and W don't separate letters of the same class; vowels do
+        if c != 'H' && c != 'W' {
+            last = current;
+        }
     }
+    format!("{:0<4}", code)
+}
 
-    // Fuzzy search
-    let fuzzy_query = SearchQuery {
-        query: "algoritms".to_string(), // Typo intentional
-        fuzzy: true,
-        ..Default::default()
+fn metaphone(letters: &[char]) -> String {
+    let is_vowel = |c: char| matches!(c, 'A' | 'E' | 'I' | 'O' | 'U');
+    let mut word: Vec<char> = letters.to_vec();
+    word.dedup_by(|a, b| a == b && *a != 'C');
+    match (word.first(), word.get(1)) {
+        (Some('K' | 'G' | 'P'), Some('N')) | (Some('A'), Some('E')) | (Some('W'), Some('R')) => {
+            word.remove(0);
+        }
+        (Some('W'), Some('H')) => {
+            word.remove(1);
+        }
+        (Some('X'), _) => word[0] = 'S',
+        _ => {}
+    }
+
+    let at = |i: isize| -> char {
+        if i < 0 { '\0' } else { word.get(i as usize).copied().unwrap_or('\0') }
+    };
+    let mut code = String::new();
+    for (i, &c) in word.iter().enumerate() {
+        let i = i as isize;
+        let (prev, next, after) = (at(i - 1), at(i + 1), at(i + 2));
+        let front_vowel = |c: char| matches!(c, 'E' | 'I' | 'Y');
+        match c {
+            'A' | 'E' | 'I' | 'O' | 'U' => {
+                if i == 0 {
+                    code.push(c);
+                }
+            }
+            'B' => {
+                if !(prev == 'M' && next == '\0') {
+                    code.push('B');
+                }
+            }
+            'C' => {
+                if next == 'I' && after == 'A' || next == 'H' && prev != 'S' {
+                    code.push('X');
+                } else if front_vowel(next) {
+                    if prev != 'S' {
+                        code.push('S');
+                    }
+                } else {
+                    code.push('K');
+                }
+            }
+            'D' => code.push(if next == 'G' && front_vowel(after) { 'J' } else { 'T' }),
+            'G' => {
+                let silent = (next == 'H' && !(after == '\0' || is_vowel(after)))
+                    || (next == 'N' && (after == '\0' || (after == 'E' && at(i + 3) == 'D')))
+                    || (prev == 'D' && front_vowel(next));
+                if !silent {
+                    code.push(if front_vowel(next) && prev != 'G' { 'J' } else { 'K' });
+                }
+            }
+            'H' => {
+                let after_modifier = matches!(prev, 'C' | 'S' | 'P' | 'T' | 'G');
+                if !after_modifier && (!is_vowel(prev) || is_vowel(next)) {
+                    code.push('H');
+                }
+            }
+            'K' => {
+                if prev != 'C' {
+                    code.push('K');
+                }
+            }
+            'P' => code.push(if next == 'H' { 'F' } else { 'P' }),
+            'Q' => code.push('K'),
+            'S' => {
+                if next == 'H' || (next == 'I' && matches!(after, 'O' | 'A')) {
+                    code.push('X');
+                } else {
+                    code.push('S');
+                }
+            }
+            'T' => {
+                if next == 'I' && matches!(after, 'O' | 'A') {
+                    code.push('X');
+                } else if next == 'H' {
+                    code.push('0');
+                } else if !(next == 'C' && after == 'H') {
+                    code.push('T');
+                }
+            }
+            'V' => code.push('F'),
+            'W' | 'Y' => {
+                if is_vowel(next) {
+                    code.push(c);
+                }
+            }
+            'X' => code.push_str("KS"),
+            'Z' => code.push('S'),
+            _ => code.push(c),
+        }
+    }
+    code
+}
+
+// ==================== PREFIX TRIE ====================
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: HashMap<char, TrieNode>,
+    terminal: bool,
+}
+
+/// Prefix tree over the indexed vocabulary.
+#[derive(Debug, Default)]
+struct TermTrie {
+    root: TrieNode,
+}
+
+impl TermTrie {
+    fn insert(&mut self, term: &str) {
+        let mut node = &mut self.root;
+        for c in term.chars() {
+            node = node.children.entry(c).or_default();
+        }
+        node.terminal = true;
+    }
+
+    fn remove(&mut self, term: &str) {
+        let chars: Vec<char> = term.chars().collect();
+        Self::remove_from(&mut self.root, &chars);
+    }
+
+    /// Unmarks the term and prunes branches left without any terms.
+    /// Returns whether `node` itself can be dropped.
+    fn remove_from(node: &mut TrieNode, chars: &[char]) -> bool {
+        match chars.split_first() {
+            None => node.terminal = false,
+            Some((c, rest)) => {
+                if let Some(child) = node.children.get_mut(c) {
+                    if Self::remove_from(child, rest) {
+                        node.children.remove(c);
+                    }
+                }
+            }
+        }
+        !node.terminal && node.children.is_empty()
+    }
+
+    /// Every stored term starting with `prefix`.
+    fn completions(&self, prefix: &str) -> Vec<String> {
+        let mut node = &self.root;
+        for c in prefix.chars() {
+            match node.children.get(&c) {
+                Some(child) => node = child,
+                None => return Vec::new(),
+            }
+        }
+
+        let mut terms = Vec::new();
+        let mut stack = vec![(node, prefix.to_string())];
+        while let Some((node, word)) = stack.pop() {
+            if node.terminal {
+                terms.push(word.clone());
+            }
+            for (c, child) in &node.children {
+                let mut next = word.clone();
+                next.push(*c);
+                stack.push((child, next));
+            }
+        }
+        terms
+    }
+}
+
+// ==================== PERSISTENCE ====================
+
+/// On-disk layout for `FerrumSearch::save_to_file` and `load_from_file`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexFormat {
+    /// Human-readable, but repeats document ids and terms everywhere.
+    Json,
+    /// Compact little-endian layout: every document id and term is written
+    /// once, and postings and positions refer to them by `u32` index.
+    Binary,
+}
+
+/// Everything needed to restore an index without re-analyzing documents.
+/// Term frequencies, the trie, numeric and phonetic indexes are derived.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexSnapshot {
+    documents: Vec<Document>,
+    postings: HashMap<String, Vec<String>>,
+    term_positions: HashMap<String, TermPositions>,
+    title_token_counts: HashMap<String, usize>,
+    document_lengths: HashMap<String, usize>,
+    indexed_at: HashMap<String, u64>,
+    surface_terms: HashMap<String, HashSet<String>>,
+}
+
+const BINARY_MAGIC: &[u8; 4] = b"FRM1";
+
+impl IndexSnapshot {
+    fn to_binary(&self) -> Vec<u8> {
+        let mut out = BinaryWriter(BINARY_MAGIC.to_vec());
+        let doc_refs: HashMap<&str, u32> = self
+            .documents
+            .iter()
+            .enumerate()
+            .map(|(i, doc)| (doc.id.as_str(), i as u32))
+            .collect();
+        let mut vocabulary: Vec<&String> = self.postings.keys().collect();
+        vocabulary.sort_unstable();
+        let term_refs: HashMap<&str, u32> = vocabulary
+            .iter()
+            .enumerate()
+            .map(|(i, term)| (term.as_str(), i as u32))
+            .collect();
+
+        out.u32(self.documents.len() as u32);
+        for doc in &self.documents {
+            out.str(&doc.id);
+            out.str(&doc.title);
+            out.str(&doc.content);
+            out.u32(doc.metadata.len() as u32);
+            for (key, value) in &doc.metadata {
+                out.str(key);
+                out.str(value);
+            }
+            out.u64(doc.timestamp);
+            out.u32(doc.numeric_metadata.len() as u32);
+            for (key, value) in &doc.numeric_metadata {
+                out.str(key);
+                out.u64(value.to_bits());
+            }
+            out.u32(doc.acl.len() as u32);
+            for group in &doc.acl {
+                out.str(group);
+            }
+            out.opt_u64(self.document_lengths.get(&doc.id).map(|&n| n as u64));
+            out.opt_u64(self.title_token_counts.get(&doc.id).map(|&n| n as u64));
+            out.opt_u64(self.indexed_at.get(&doc.id).copied());
+            let surface = self.surface_terms.get(&doc.id);
+            out.u32(surface.map_or(0, HashSet::len) as u32);
+            for word in surface.into_iter().flatten() {
+                out.str(word);
+            }
+        }
+
+        out.u32(vocabulary.len() as u32);
+        for term in &vocabulary {
+            out.str(term);
+            let refs: Vec<u32> = self.postings[*term]
+                .iter()
+                .filter_map(|id| doc_refs.get(id.as_str()).copied())
+                .collect();
+            out.u32(refs.len() as u32);
+            for doc_ref in refs {
+                out.u32(doc_ref);
+            }
+        }
+
+        for doc in &self.documents {
+            let positions: Vec<(u32, &Vec<usize>)> = self
+                .term_positions
+                .get(&doc.id)
+                .into_iter()
+                .flatten()
+                .filter_map(|(term, at)| Some((*term_refs.get(term.as_str())?, at)))
+                .collect();
+            out.u32(positions.len() as u32);
+            for (term_ref, at) in positions {
+                out.u32(term_ref);
+                out.u32(at.len() as u32);
+                for &position in at {
+                    out.u32(position as u32);
+                }
+            }
+        }
+        out.0
+    }
+
+    fn from_binary(bytes: &[u8]) -> Result<Self, FerrumError> {
+        let mut input = BinaryReader { bytes, pos: 0 };
+        if input.take(BINARY_MAGIC.len())? != BINARY_MAGIC {
+            return Err(FerrumError::Serialization("not a binary index file".to_string()));
+        }
+
+        let mut snapshot = IndexSnapshot::default();
+        for _ in 0..input.u32()? {
+            let id = input.str()?;
+            let title = input.str()?;
+            let content = input.str()?;
+            let mut metadata = HashMap::new();
+            for _ in 0..input.u32()? {
+                metadata.insert(input.str()?, input.str()?);
+            }
+            let timestamp = input.u64()?;
+            let mut numeric_metadata = HashMap::new();
+            for _ in 0..input.u32()? {
+                numeric_metadata.insert(input.str()?, f64::from_bits(input.u64()?));
+            }
+            let acl = (0..input.u32()?).map(|_| input.str()).collect::<Result<_, _>>()?;
+            if let Some(length) = input.opt_u64()? {
+                snapshot.document_lengths.insert(id.clone(), length as usize);
+            }
+            if let Some(count) = input.opt_u64()? {
+                snapshot.title_token_counts.insert(id.clone(), count as usize);
+            }
+            if let Some(at) = input.opt_u64()? {
+                snapshot.indexed_at.insert(id.clone(), at);
+            }
+            let surface: HashSet<String> = (0..input.u32()?).map(|_| input.str()).collect::<Result<_, _>>()?;
+            if !surface.is_empty() {
+                snapshot.surface_terms.insert(id.clone(), surface);
+            }
+            snapshot.documents.push(Document { id, title, content, metadata, timestamp, numeric_metadata, acl });
+        }
+
+        let doc_id = |doc_ref: u32, snapshot: &IndexSnapshot| {
+            snapshot
+                .documents
+                .get(doc_ref as usize)
+                .map(|doc| doc.id.clone())
+                .ok_or_else(|| FerrumError::Serialization(format!("dangling document reference {}", doc_ref)))
+        };
+        let mut vocabulary = Vec::new();
+        for _ in 0..input.u32()? {
+            let term = input.str()?;
+            let postings = (0..input.u32()?)
+                .map(|_| doc_id(input.u32()?, &snapshot))
+                .collect::<Result<_, _>>()?;
+            snapshot.postings.insert(term.clone(), postings);
+            vocabulary.push(term);
+        }
+
+        for i in 0..snapshot.documents.len() {
+            let mut positions = TermPositions::new();
+            for _ in 0..input.u32()? {
+                let term_ref = input.u32()? as usize;
+                let term = vocabulary
+                    .get(term_ref)
+                    .ok_or_else(|| FerrumError::Serialization(format!("dangling term reference {}", term_ref)))?;
+                let at = (0..input.u32()?).map(|_| input.u32().map(|p| p as usize)).collect::<Result<_, _>>()?;
+                positions.insert(term.clone(), at);
+            }
+            if !positions.is_empty() {
+                snapshot.term_positions.insert(snapshot.documents[i].id.clone(), positions);
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+struct BinaryWriter(Vec<u8>);
+
+impl BinaryWriter {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn opt_u64(&mut self, value: Option<u64>) {
+        match value {
+            Some(value) => {
+                self.0.push(1);
+                self.u64(value);
+            }
+            None => self.0.push(0),
+        }
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+}
+
+struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl BinaryReader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], FerrumError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| FerrumError::Serialization("binary index file is truncated".to_string()))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, FerrumError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, FerrumError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn opt_u64(&mut self) -> Result<Option<u64>, FerrumError> {
+        match self.take(1)?[0] {
+            0 => Ok(None),
+            _ => self.u64().map(Some),
+        }
+    }
+
+    fn str(&mut self) -> Result<String, FerrumError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| FerrumError::Serialization(e.to_string()))
+    }
+}
+
+// ==================== SEARCH ENGINE CORE ====================
+
+/// Index mutation reported to the change listener. `Added` covers both new
+/// documents and updates of existing ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeEvent {
+    Added(String),
+    Removed(String),
+    Cleared,
+    /// The whole index was swapped by `load_from_file` or `replace_with`.
+    Replaced,
+}
+
+/// Self-contained form of a `ChangeEvent` streamed to `subscribe_changes`
+/// receivers, so a replica can replay it with `apply_change`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationEvent {
+    Upserted(Document),
+    Removed(String),
+    Cleared,
+    Replaced(Vec<Document>),
+}
+
+pub type ChangeListener = Box<dyn Fn(ChangeEvent) + Send + Sync>;
+
+/// A mutation's change event, numbered in mutation order, waiting to be sent.
+struct PendingChange {
+    sequence: u64,
+    event: ChangeEvent,
+    replication: Option<ReplicationEvent>,
+}
+
+/// Changes held back until every earlier one has been sent.
+#[derive(Default)]
+struct ChangeQueue {
+    next_to_send: u64,
+    waiting: BTreeMap<u64, PendingChange>,
+}
+
+/// Re-ranking hook: receives the top candidates in BM25 order and may reorder,
+/// rescore, or drop them before pagination.
+pub type RerankFn = Box<dyn Fn(&mut Vec<SearchResult>) + Send + Sync>;
+
+/// Time source for `indexed_at` and `last_updated`, in seconds since the epoch.
+pub type Clock = Box<dyn Fn() -> u64 + Send + Sync>;
+
+/// Largest page size `validate_query` accepts.
+pub const MAX_PER_PAGE: usize = 1000;
+
+/// Postings scored between checks of a search's cancellation token.
+const CANCELLATION_CHECK_INTERVAL: usize = 256;
+
+
+/// Document fields that are tokenized into the index.
+const ANALYZED_FIELDS: [&str; 2] = ["title", "content"];
+
+/// Token offsets of every term within a single document.
+type TermPositions = HashMap<String, Vec<usize>>;
+
+/// `(value, doc_id)` pairs for one numeric key, sorted by value.
+type NumericPostings = Vec<(f64, String)>;
+
+/// Dense stand-in for a document id inside posting lists.
+type DocHandle = u32;
+
+/// Interns document ids as `DocHandle`s; freed handles are reused.
+#[derive(Debug, Default)]
+struct DocInterner {
+    ids: Vec<String>,
+    handles: HashMap<String, DocHandle>,
+    free: Vec<DocHandle>,
+}
+
+impl DocInterner {
+    fn intern(&mut self, id: &str) -> DocHandle {
+        if let Some(&handle) = self.handles.get(id) {
+            return handle;
+        }
+        let handle = match self.free.pop() {
+            Some(handle) => {
+                self.ids[handle as usize] = id.to_string();
+                handle
+            }
+            None => {
+                self.ids.push(id.to_string());
+                (self.ids.len() - 1) as DocHandle
+            }
+        };
+        self.handles.insert(id.to_string(), handle);
+        handle
+    }
+
+    fn handle(&self, id: &str) -> Option<DocHandle> {
+        self.handles.get(id).copied()
+    }
+
+    fn id(&self, handle: DocHandle) -> &str {
+        &self.ids[handle as usize]
+    }
+
+    /// Frees `id`'s handle; callers must already have dropped its postings.
+    fn release(&mut self, id: &str) {
+        if let Some(handle) = self.handles.remove(id) {
+            self.ids[handle as usize].clear();
+            self.free.push(handle);
+        }
+    }
+
+    /// Renumbers live handles densely, returning the new handle of each old one.
+    fn compact(&mut self) -> Vec<DocHandle> {
+        let mut remap = vec![DocHandle::MAX; self.ids.len()];
+        for (old, id) in std::mem::take(&mut self.ids).into_iter().enumerate() {
+            if let Some(handle) = self.handles.get_mut(&id).filter(|handle| **handle as usize == old) {
+                *handle = self.ids.len() as DocHandle;
+                remap[old] = *handle;
+                self.ids.push(id);
+            }
+        }
+        self.free.clear();
+        remap
+    }
+}
+
+/// A metadata value used for sorting: numbers order numerically and before text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldValue<'a> {
+    Number(f64),
+    Text(&'a str),
+}
+
+impl<'a> FieldValue<'a> {
+    /// Metadata first, then the built-in `timestamp` and `title` fields.
+    fn of(doc: &'a Document, key: &str) -> Option<Self> {
+        doc.numeric_metadata
+            .get(key)
+            .map(|n| FieldValue::Number(*n))
+            .or_else(|| doc.metadata.get(key).map(|s| FieldValue::Text(s)))
+            .or(match key {
+                "timestamp" => Some(FieldValue::Number(doc.timestamp as f64)),
+                "title" => Some(FieldValue::Text(&doc.title)),
+                _ => None,
+            })
+    }
+
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
+        match (self, other) {
+            (FieldValue::Number(a), FieldValue::Number(b)) => a.total_cmp(b),
+            (FieldValue::Text(a), FieldValue::Text(b)) => a.cmp(b),
+            (FieldValue::Number(_), FieldValue::Text(_)) => Ordering::Less,
+            (FieldValue::Text(_), FieldValue::Number(_)) => Ordering::Greater,
+        }
+    }
+}
+
+/// How `add_document` assigns an id to documents submitted without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IdStrategy {
+    /// A fresh random UUID every time.
+    #[default]
+    Random,
+    /// A stable hash of title and content, so re-importing the same document
+    /// updates it instead of creating a duplicate.
+    ContentHash,
+}
+
+/// What `add_document` does with documents that produce no tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EmptyDocumentPolicy {
+    /// Store it (findable by filters and id) with a nominal length of 1.
+    #[default]
+    Index,
+    /// Refuse it with an error.
+    Reject,
+}
+
+/// What `add_document` does with content longer than `max_content_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OversizedContentPolicy {
+    /// Refuse the document with `FerrumError::ContentTooLarge`.
+    #[default]
+    Reject,
+    /// Cut the content at the limit (on a character boundary) and record the
+    /// original size under the `ORIGINAL_CONTENT_BYTES_KEY` metadata key.
+    Truncate,
+}
+
+/// Metadata key holding the byte length of content cut by `OversizedContentPolicy::Truncate`.
+pub const ORIGINAL_CONTENT_BYTES_KEY: &str = "original_content_bytes";
+
+/// Rules document ids must follow once `set_id_policy` enables validation.
+/// Whitespace and control characters are always rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdPolicy {
+    /// Longest accepted id, in bytes.
+    pub max_len: usize,
+    /// Characters accepted besides ASCII letters and digits; `None` accepts
+    /// any other printable character.
+    pub allowed_symbols: Option<String>,
+}
+
+impl Default for IdPolicy {
+    fn default() -> Self {
+        Self {
+            max_len: 256,
+            allowed_symbols: None,
+        }
+    }
+}
+
+impl IdPolicy {
+    fn check(&self, id: &str) -> Result<(), String> {
+        if id.len() > self.max_len {
+            return Err(format!("longer than {} bytes", self.max_len));
+        }
+        if let Some(c) = id.chars().find(|c| c.is_whitespace() || c.is_control()) {
+            return Err(format!("contains whitespace or control character {:?}", c));
+        }
+        if let Some(symbols) = &self.allowed_symbols {
+            if let Some(c) = id.chars().find(|&c| !c.is_ascii_alphanumeric() && !symbols.contains(c)) {
+                return Err(format!("contains disallowed character {:?}", c));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a term's title and content occurrences combine into its frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum FieldCombine {
+    /// Every occurrence counts once, wherever it is.
+    #[default]
+    Sum,
+    /// Only the field with more occurrences counts.
+    Max,
+    /// Occurrences are scaled by a per-field weight before summing.
+    WeightedSum { title: f32, content: f32 },
+}
+
+impl FieldCombine {
+    fn combine(&self, title: usize, content: usize) -> f32 {
+        let (title, content) = (title as f32, content as f32);
+        match *self {
+            FieldCombine::Sum => title + content,
+            FieldCombine::Max => title.max(content),
+            FieldCombine::WeightedSum { title: wt, content: wc } => wt * title + wc * content,
+        }
+    }
+}
+
+/// The curve that turns a term's frequency into its score contribution.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum TfSaturation {
+    /// Classic BM25 saturation with length normalization.
+    #[default]
+    Bm25,
+    /// `ln(1 + occurrences)`, with no length normalization.
+    LogTf,
+    /// BM25L: BM25 with a lower-bound shift that stops long documents
+    /// from being over-penalized.
+    Bm25L,
+}
+
+impl TfSaturation {
+    /// BM25L's lower-bound shift.
+    const BM25L_DELTA: f32 = 0.5;
+
+    fn saturate(&self, tf: f32, doc_len: usize, avg_doc_len: f32, k1: f32, b: f32) -> f32 {
+        let length_norm = 1.0 - b + b * (doc_len as f32 / avg_doc_len);
+        match *self {
+            TfSaturation::Bm25 => (tf * (k1 + 1.0)) / (tf + k1 * length_norm),
+            TfSaturation::LogTf => (1.0 + tf * doc_len as f32).ln(),
+            TfSaturation::Bm25L => {
+                let shifted = tf / length_norm + Self::BM25L_DELTA;
+                (k1 + 1.0) * shifted / (k1 + shifted)
+            }
+        }
+    }
+}
+
+/// Engine-wide tuning knobs, adjusted through the `set_*` methods.
+#[derive(Debug, Clone)]
+struct EngineSettings {
+    max_expansions: usize,
+    proximity_boost: f32,
+    max_indexed_tokens: Option<usize>,
+    exact_match_boost: f32,
+    id_strategy: IdStrategy,
+    empty_document_policy: EmptyDocumentPolicy,
+    id_policy: Option<IdPolicy>,
+    max_content_bytes: Option<usize>,
+    oversized_content_policy: OversizedContentPolicy,
+    search_shards: usize,
+    min_postings_per_shard: usize,
+    min_term_score: Option<f32>,
+    field_combine: FieldCombine,
+    deterministic: bool,
+    searchable_metadata: Vec<String>,
+    tf_saturation: TfSaturation,
+    phonetic: Option<PhoneticAlgorithm>,
+    full_match_bonus: Option<f32>,
+    exact_title_boost: Option<f32>,
+}
+
+impl Default for EngineSettings {
+    fn default() -> Self {
+        Self {
+            max_expansions: 256,
+            proximity_boost: 0.0,
+            max_indexed_tokens: None,
+            exact_match_boost: 1.0,
+            id_strategy: IdStrategy::Random,
+            empty_document_policy: EmptyDocumentPolicy::Index,
+            id_policy: None,
+            max_content_bytes: None,
+            oversized_content_policy: OversizedContentPolicy::Reject,
+            search_shards: 1,
+            min_postings_per_shard: 4096,
+            min_term_score: None,
+            field_combine: FieldCombine::Sum,
+            deterministic: false,
+            searchable_metadata: Vec::new(),
+            tf_saturation: TfSaturation::Bm25,
+            phonetic: None,
+            full_match_bonus: None,
+            exact_title_boost: None,
+        }
+    }
+}
+
+/// LRU cache of search responses, valid for one index generation.
+struct QueryCache {
+    capacity: usize,
+    generation: u64,
+    entries: HashMap<String, SearchResponse>,
+    /// Keys from least to most recently used.
+    order: VecDeque<String>,
+    hits: u64,
+}
+
+impl QueryCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            generation: 0,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            hits: 0,
+        }
+    }
+
+    /// Normalized query text plus every other option, with sorted map keys.
+    fn key(query: &SearchQuery) -> String {
+        let mut normalized = query.clone();
+        normalized.query = query.query.split_whitespace().collect::<Vec<_>>().join(" ");
+        serde_json::to_value(&normalized).map(|v| v.to_string()).unwrap_or_default()
+    }
+
+    fn sync_generation(&mut self, generation: u64) {
+        if self.generation != generation {
+            self.entries.clear();
+            self.order.clear();
+            self.generation = generation;
+        }
+    }
+
+    fn get(&mut self, key: &str, generation: u64) -> Option<SearchResponse> {
+        self.sync_generation(generation);
+        let response = self.entries.get(key)?.clone();
+        if let Some(at) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(at).unwrap();
+            self.order.push_back(key);
+        }
+        self.hits += 1;
+        Some(response)
+    }
+
+    fn insert(&mut self, key: String, response: SearchResponse, generation: u64) {
+        self.sync_generation(generation);
+        if self.capacity == 0 || self.entries.contains_key(&key) {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.entries.remove(&evicted);
+            }
+        }
+        self.order.push_back(key.clone());
+        self.entries.insert(key, response);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+pub struct FerrumSearch {
+    documents: Arc<RwLock<HashMap<String, Document>>>,
+    /// One posting per document containing the term.
+    inverted_index: Arc<RwLock<HashMap<String, Vec<DocHandle>>>>,
+    /// Postings of title terms only, for title-scoped searches.
+    title_index: Arc<RwLock<HashMap<String, Vec<DocHandle>>>>,
+    doc_handles: Arc<RwLock<DocInterner>>,
+    word_frequencies: Arc<RwLock<HashMap<String, HashMap<String, f32>>>>,
+    document_lengths: Arc<RwLock<HashMap<String, usize>>>,
+    /// Running mean of `document_lengths`.
+    avg_doc_len: Arc<RwLock<f64>>,
+    term_positions: Arc<RwLock<HashMap<String, TermPositions>>>,
+    /// Number of leading positions in each document produced by its title.
+    title_token_counts: Arc<RwLock<HashMap<String, usize>>>,
+    /// When each document was last (re)indexed, in seconds since the epoch.
+    indexed_at: Arc<RwLock<HashMap<String, u64>>>,
+    surface_terms: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    term_trie: Arc<RwLock<TermTrie>>,
+    /// Phonetic code to indexed terms; may hold terms no longer indexed.
+    phonetic_index: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    numeric_index: Arc<RwLock<HashMap<String, NumericPostings>>>,
+    total_documents: Arc<RwLock<usize>>,
+    settings: Arc<RwLock<EngineSettings>>,
+    search_defaults: Arc<RwLock<SearchDefaults>>,
+    analyzer: Arc<RwLock<Analyzer>>,
+    field_analyzers: Arc<RwLock<HashMap<String, Analyzer>>>,
+    stop_words: Arc<RwLock<HashSet<String>>>,
+    reranker: Arc<RwLock<Option<(usize, RerankFn)>>>,
+    clock: Arc<RwLock<Option<Clock>>>,
+    /// Ticks of the deterministic-mode clock used when none was injected.
+    logical_clock: Arc<AtomicU64>,
+    change_listener: Arc<RwLock<Option<ChangeListener>>>,
+    change_subscribers: Arc<RwLock<Vec<Sender<ReplicationEvent>>>>,
+    /// Number of the next recorded change.
+    change_sequence: Arc<AtomicU64>,
+    change_queue: Arc<RwLock<ChangeQueue>>,
+    /// Held shared by searches and mutations, exclusively by `replace_with`,
+    /// so a swap never interleaves with a multi-lock operation.
+    swap_lock: Arc<RwLock<()>>,
+    /// Incremented by every mutation of indexed data.
+    generation: Arc<RwLock<u64>>,
+    /// Records of the most recent searches, oldest first.
+    query_log: Arc<RwLock<VecDeque<QueryRecord>>>,
+    query_log_capacity: Arc<RwLock<usize>>,
+    /// Removals and replacements since the last `optimize`.
+    deleted_since_optimize: Arc<RwLock<usize>>,
+    query_cache: Arc<RwLock<Option<QueryCache>>>,
+    query_metrics: Arc<RwLock<QueryMetrics>>,
+}
+
+impl Default for FerrumSearch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FerrumSearch {
+    pub fn new() -> Self {
+        Self {
+            documents: Arc::new(RwLock::new(HashMap::new())),
+            inverted_index: Arc::new(RwLock::new(HashMap::new())),
+            title_index: Arc::new(RwLock::new(HashMap::new())),
+            doc_handles: Arc::new(RwLock::new(DocInterner::default())),
+            word_frequencies: Arc::new(RwLock::new(HashMap::new())),
+            document_lengths: Arc::new(RwLock::new(HashMap::new())),
+            avg_doc_len: Arc::new(RwLock::new(0.0)),
+            term_positions: Arc::new(RwLock::new(HashMap::new())),
+            title_token_counts: Arc::new(RwLock::new(HashMap::new())),
+            indexed_at: Arc::new(RwLock::new(HashMap::new())),
+            surface_terms: Arc::new(RwLock::new(HashMap::new())),
+            term_trie: Arc::new(RwLock::new(TermTrie::default())),
+            phonetic_index: Arc::new(RwLock::new(HashMap::new())),
+            numeric_index: Arc::new(RwLock::new(HashMap::new())),
+            total_documents: Arc::new(RwLock::new(0)),
+            settings: Arc::new(RwLock::new(EngineSettings::default())),
+            search_defaults: Arc::new(RwLock::new(SearchDefaults::default())),
+            analyzer: Arc::new(RwLock::new(Analyzer::default())),
+            field_analyzers: Arc::new(RwLock::new(HashMap::new())),
+            stop_words: Arc::new(RwLock::new(HashSet::new())),
+            reranker: Arc::new(RwLock::new(None)),
+            clock: Arc::new(RwLock::new(None)),
+            logical_clock: Arc::new(AtomicU64::new(0)),
+            change_listener: Arc::new(RwLock::new(None)),
+            change_subscribers: Arc::new(RwLock::new(Vec::new())),
+            change_sequence: Arc::new(AtomicU64::new(0)),
+            change_queue: Arc::new(RwLock::new(ChangeQueue::default())),
+            swap_lock: Arc::new(RwLock::new(())),
+            generation: Arc::new(RwLock::new(0)),
+            deleted_since_optimize: Arc::new(RwLock::new(0)),
+            query_log: Arc::new(RwLock::new(VecDeque::new())),
+            query_log_capacity: Arc::new(RwLock::new(1000)),
+            query_metrics: Arc::new(RwLock::new(QueryMetrics::default())),
+            query_cache: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    // ==================== CONFIGURATION ====================
+
+    /// Caps how many indexed terms a single fuzzy or prefix token may expand
+    /// to. The most frequent terms are kept when the cap is hit.
+    pub fn set_max_expansions(&self, max_expansions: usize) {
+        self.settings.write().unwrap().max_expansions = max_expansions;
+        self.invalidate_query_cache();
+    }
+
+    /// Adds `factor / d` to a document's score, where `d` is the smallest
+    /// token distance between two different query terms. Zero disables it.
+    pub fn set_proximity_boost(&self, factor: f32) {
+        self.settings.write().unwrap().proximity_boost = factor;
+        self.invalidate_query_cache();
+    }
+
+    /// Stops indexing a document after this many tokens. The full content is
+    /// still stored and returned; only postings and length are bounded.
+    pub fn set_max_indexed_tokens(&self, max_indexed_tokens: Option<usize>) {
+        self.settings.write().unwrap().max_indexed_tokens = max_indexed_tokens;
+    }
+
+    /// Scales term scores where the query word occurs exactly as typed; `1.0` disables it.
+    pub fn set_exact_match_boost(&self, boost: f32) {
+        self.settings.write().unwrap().exact_match_boost = boost;
+        self.invalidate_query_cache();
+    }
+
+    pub fn set_id_strategy(&self, strategy: IdStrategy) {
+        self.settings.write().unwrap().id_strategy = strategy;
+    }
+
+    pub fn set_empty_document_policy(&self, policy: EmptyDocumentPolicy) {
+        self.settings.write().unwrap().empty_document_policy = policy;
+    }
+
+    /// Scores queries on up to `shards` threads; results match the default of 1.
+    pub fn set_search_shards(&self, shards: usize) {
+        self.settings.write().unwrap().search_shards = shards;
+    }
+
+    /// Fewest postings worth a scoring thread (default 4096).
+    pub fn set_min_postings_per_shard(&self, min_postings: usize) {
+        self.settings.write().unwrap().min_postings_per_shard = min_postings.max(1);
+    }
+
+    /// Caps the content size of documents added from now on, handling larger
+    /// ones per `policy`. `None` (the default) accepts any size.
+    pub fn set_max_content_bytes(&self, limit: Option<usize>, policy: OversizedContentPolicy) {
+        let mut settings = self.settings.write().unwrap();
+        settings.max_content_bytes = limit;
+        settings.oversized_content_policy = policy;
+    }
+
+    /// Validates the ids of documents added from now on; `None` (the
+    /// default) accepts any id. Generated ids are checked too.
+    pub fn set_id_policy(&self, policy: Option<IdPolicy>) {
+        self.settings.write().unwrap().id_policy = policy;
+    }
+
+    /// Floor for each matched term's score, so negative-IDF matches still count.
+    pub fn set_min_term_score(&self, floor: Option<f32>) {
+        self.settings.write().unwrap().min_term_score = floor;
+        self.invalidate_query_cache();
+    }
+
+    /// Chooses how title and content occurrences of a term are combined
+    /// into its term frequency.
+    pub fn set_field_combine(&self, combine: FieldCombine) {
+        self.settings.write().unwrap().field_combine = combine;
+        self.invalidate_query_cache();
+    }
+
+    /// Chooses the term-frequency saturation curve used when scoring.
+    pub fn set_tf_saturation(&self, saturation: TfSaturation) {
+        self.settings.write().unwrap().tf_saturation = saturation;
+        self.invalidate_query_cache();
+    }
+
+    /// Enables (or, with `None`, disables) the phonetic index used by
+    /// `SearchQuery::phonetic`, re-encoding the current vocabulary.
+    pub fn set_phonetic(&self, algorithm: Option<PhoneticAlgorithm>) {
+        // Exclusive, like the other whole-index rebuilds, so no document is
+        // indexed under the old setting while the codes are recomputed
+        let _swap_guard = self.swap_lock.write().unwrap();
+        self.settings.write().unwrap().phonetic = algorithm;
+        let mut codes: HashMap<String, HashSet<String>> = HashMap::new();
+        if let Some(algorithm) = algorithm {
+            let terms: Vec<String> = self.inverted_index.read().unwrap().keys().cloned().collect();
+            for term in terms {
+                if let Some(code) = algorithm.encode(&term) {
+                    codes.entry(code).or_default().insert(term);
+                }
+            }
+        }
+        *self.phonetic_index.write().unwrap() = codes;
+        self.invalidate_query_cache();
+    }
+
+    fn index_phonetic(&self, term: &str) {
+        let Some(algorithm) = self.settings.read().unwrap().phonetic else {
+            return;
+        };
+        if let Some(code) = algorithm.encode(term) {
+            self.phonetic_index.write().unwrap().entry(code).or_default().insert(term.to_string());
+        }
+    }
+
+    /// Adds `bonus` to documents matching every query term. `None` disables it.
+    pub fn set_full_match_bonus(&self, bonus: Option<f32>) {
+        self.settings.write().unwrap().full_match_bonus = bonus;
+        self.invalidate_query_cache();
+    }
+
+    /// Adds `boost` to documents titled exactly like the query. Off by default.
+    pub fn set_exact_title_boost(&self, boost: Option<f32>) {
+        self.settings.write().unwrap().exact_title_boost = boost;
+        self.invalidate_query_cache();
+    }
+
+    /// Indexes these metadata values as content. Only affects later documents.
+    pub fn set_searchable_metadata(&self, keys: Vec<String>) {
+        self.settings.write().unwrap().searchable_metadata = keys;
+        self.invalidate_query_cache();
+    }
+
+    /// Replaces the wall clock used for `indexed_at` and `last_updated`.
+    pub fn set_clock(&self, clock: Clock) {
+        *self.clock.write().unwrap() = Some(clock);
+    }
+
+    /// Reproducible output: zero query times, ties ordered by id, and (unless a
+    /// clock was injected) a logical clock counting indexing writes.
+    pub fn set_deterministic(&self, enabled: bool) {
+        let mut settings = self.settings.write().unwrap();
+        if enabled && !settings.deterministic {
+            self.logical_clock.store(0, atomic::Ordering::Relaxed);
+        }
+        settings.deterministic = enabled;
+        drop(settings);
+        self.invalidate_query_cache();
+    }
+
+    /// Timestamp for a write, advancing the logical clock in deterministic mode.
+    fn now(&self) -> u64 {
+        self.read_clock(true)
+    }
+
+    /// Timestamp for a read, which leaves the logical clock where it is.
+    fn current_time(&self) -> u64 {
+        self.read_clock(false)
+    }
+
+    fn read_clock(&self, tick: bool) -> u64 {
+        if let Some(clock) = self.clock.read().unwrap().as_ref() {
+            return clock();
+        }
+        if !self.settings.read().unwrap().deterministic {
+            return now_secs();
+        }
+        if tick {
+            self.logical_clock.fetch_add(1, atomic::Ordering::Relaxed)
+        } else {
+            self.logical_clock.load(atomic::Ordering::Relaxed)
+        }
+    }
+
+    /// Re-ranks the `top_k` best candidates of every search before pagination.
+    pub fn set_reranker(&self, top_k: usize, reranker: RerankFn) {
+        *self.reranker.write().unwrap() = Some((top_k, reranker));
+        self.invalidate_query_cache();
+    }
+
+    pub fn clear_reranker(&self) {
+        *self.reranker.write().unwrap() = None;
+        self.invalidate_query_cache();
+    }
+
+    /// Called in mutation order after each mutation, once the index reflects it
+    /// and locks are released. It may search the engine but must not mutate it.
+    pub fn set_change_listener(&self, listener: ChangeListener) {
+        *self.change_listener.write().unwrap() = Some(listener);
+    }
+
+    /// Returns a channel receiving every subsequent change event, in order.
+    /// Dropping the receiver unsubscribes it.
+    pub fn subscribe_changes(&self) -> Receiver<ReplicationEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.change_subscribers.write().unwrap().push(sender);
+        receiver
+    }
+
+    /// Replays a change event from another engine (see `subscribe_changes`)
+    /// onto this one.
+    pub fn apply_change(&self, event: ReplicationEvent) -> Result<(), FerrumError> {
+        match event {
+            ReplicationEvent::Upserted(document) => self.add_document(document),
+            ReplicationEvent::Removed(doc_id) => self.remove_document(&doc_id),
+            ReplicationEvent::Cleared => self.clear_index(),
+            ReplicationEvent::Replaced(documents) => {
+                self.clear_index()?;
+                documents.into_iter().try_for_each(|document| self.add_document(document))
+            }
+        }
+    }
+
+    /// Numbers a change under the `documents` write lock; pass it to `notify`
+    /// once the locks are released.
+    fn record_change(&self, event: ChangeEvent, replicate: impl FnOnce() -> ReplicationEvent) -> PendingChange {
+        let replication = if self.change_subscribers.read().unwrap().is_empty() {
+            None
+        } else {
+            Some(replicate())
+        };
+        PendingChange {
+            sequence: self.change_sequence.fetch_add(1, atomic::Ordering::Relaxed),
+            event,
+            replication,
+        }
+    }
+
+    /// Sends `change` and any held-back successors, in sequence order.
+    fn notify(&self, change: PendingChange) {
+        let mut queue = self.change_queue.write().unwrap();
+        queue.waiting.insert(change.sequence, change);
+        loop {
+            let next = queue.next_to_send;
+            let Some(change) = queue.waiting.remove(&next) else { break };
+            queue.next_to_send += 1;
+            if let Some(listener) = self.change_listener.read().unwrap().as_ref() {
+                listener(change.event);
+            }
+            if let Some(event) = change.replication {
+                self.change_subscribers.write().unwrap().retain(|sender| sender.send(event.clone()).is_ok());
+            }
+        }
+    }
+
+    /// Replaces the analysis pipeline shared by indexing and querying.
+    /// Only affects documents indexed after the change.
+    pub fn set_analyzer(&self, analyzer: Analyzer) {
+        *self.analyzer.write().unwrap() = analyzer;
+        self.invalidate_query_cache();
+    }
+
+    /// Adds the words in `path`, one per line, to the stop words; returns how many were new.
+    pub fn load_stop_words_from_file(&self, path: &Path) -> Result<usize, FerrumError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| FerrumError::Io(format!("Failed to read {}: {}", path.display(), e)))?;
+        let mut stop_words = self.stop_words.write().unwrap();
+        let added = text
+            .lines()
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .filter(|word| stop_words.insert(word.to_lowercase()))
+            .count();
+        drop(stop_words);
+        self.invalidate_query_cache();
+        Ok(added)
+    }
+
+    /// Sets the fallbacks applied to queries that leave the operator,
+    /// `per_page`, highlighting, or searched fields at their defaults.
+    pub fn set_search_defaults(&self, defaults: SearchDefaults) {
+        *self.search_defaults.write().unwrap() = defaults;
+        self.invalidate_query_cache();
+    }
+
+    /// Overrides the analyzer for one indexed field (`"title"` or `"content"`).
+    pub fn set_field_analyzer(&self, field: &str, analyzer: Analyzer) -> Result<(), FerrumError> {
+        if !ANALYZED_FIELDS.contains(&field) {
+            return Err(FerrumError::UnknownField(field.to_string()));
+        }
+        self.field_analyzers.write().unwrap().insert(field.to_string(), analyzer);
+        self.invalidate_query_cache();
+        Ok(())
+    }
+
+    /// Caches up to `capacity` search responses until the index changes. Zero disables it.
+    pub fn set_query_cache(&self, capacity: usize) {
+        *self.query_cache.write().unwrap() = (capacity > 0).then(|| QueryCache::new(capacity));
+    }
+
+    /// Bounds how many past queries `popular_queries` learns from (default
+    /// 1000). Zero disables the query log.
+    pub fn set_query_log_capacity(&self, capacity: usize) {
+        *self.query_log_capacity.write().unwrap() = capacity;
+        let mut log = self.query_log.write().unwrap();
+        while log.len() > capacity {
+            log.pop_front();
+        }
+    }
+
+    fn invalidate_query_cache(&self) {
+        if let Some(cache) = self.query_cache.write().unwrap().as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// Counter advanced by every mutation of indexed data, for detecting
+    /// stale search results (see `SearchResponse::index_generation`).
+    pub fn index_generation(&self) -> u64 {
+        *self.generation.read().unwrap()
+    }
+
+    fn bump_generation(&self) {
+        *self.generation.write().unwrap() += 1;
+    }
+
+    /// Shorthand for the default analyzer followed by a `Stem` filter
+    /// ("running" -> "run"). Disabling restores the default analyzer.
+    pub fn set_stemming(&self, enabled: bool) {
+        let analyzer = if enabled {
+            Analyzer::default().with_filter(Stem)
+        } else {
+            Analyzer::default()
+        };
+        self.set_analyzer(analyzer);
+    }
+
+    // ==================== INDEXING OPERATIONS ====================
+
+    pub fn add_document(&self, document: Document) -> Result<(), FerrumError> {
+        self.index_document(document, true)
+    }
+
+    /// Insert-only variant of `add_document`: fails instead of overwriting
+    /// when a document with the same id is already indexed.
+    pub fn insert_document(&self, document: Document) -> Result<(), FerrumError> {
+        self.index_document(document, false)
+    }
+
+    fn index_document(&self, mut document: Document, overwrite: bool) -> Result<(), FerrumError> {
+        let swap_guard = self.swap_lock.read()?;
+        if document.id.is_empty() {
+            document.id = match self.settings.read().unwrap().id_strategy {
+                IdStrategy::Random => Uuid::new_v4().to_string(),
+                IdStrategy::ContentHash => content_hash_id(&document),
+            };
+        }
+        if let Some(policy) = &self.settings.read().unwrap().id_policy {
+            policy.check(&document.id).map_err(|reason| FerrumError::InvalidId {
+                id: document.id.clone(),
+                reason,
+            })?;
+        }
+        let (max_content_bytes, oversized_policy) = {
+            let settings = self.settings.read().unwrap();
+            (settings.max_content_bytes, settings.oversized_content_policy)
+        };
+        if let Some(limit) = max_content_bytes.filter(|&limit| document.content.len() > limit) {
+            let bytes = document.content.len();
+            match oversized_policy {
+                OversizedContentPolicy::Reject => {
+                    return Err(FerrumError::ContentTooLarge { id: document.id, bytes, limit });
+                }
+                OversizedContentPolicy::Truncate => {
+                    let mut end = limit;
+                    while !document.content.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    document.content.truncate(end);
+                    document.metadata.insert(ORIGINAL_CONTENT_BYTES_KEY.to_string(), bytes.to_string());
+                }
+            }
+        }
+
+        let doc_id = document.id.clone();
+        let numeric_values = document.numeric_metadata.clone();
+        let (tokens, title_token_count, text) = self.analyze_document(&document);
+        let empty_policy = self.settings.read().unwrap().empty_document_policy;
+        let surface_words: HashSet<String> = self.analyzer.read().unwrap()
+            .surface_words(&text)
+            .into_iter()
+            .collect();
+        
+        // Store document
+        let change = {
+            let mut docs = self.documents.write().unwrap();
+            let is_new = !docs.contains_key(&doc_id);
+            if !is_new && !overwrite {
+                return Err(FerrumError::DuplicateId(doc_id));
+            }
+            if tokens.is_empty() && empty_policy == EmptyDocumentPolicy::Reject {
+                return Err(FerrumError::EmptyDocument(doc_id));
+            }
+            docs.insert(doc_id.clone(), document);
+            
+            if is_new {
+                let mut total = self.total_documents.write().unwrap();
+                *total += 1;
+            } else {
+                *self.deleted_since_optimize.write().unwrap() += 1;
+            }
+            let stored = &docs[&doc_id];
+            self.record_change(ChangeEvent::Added(doc_id.clone()), || ReplicationEvent::Upserted(stored.clone()))
+        };
+
+        // Update inverted index and frequencies
+        {
+            let mut index = self.inverted_index.write().unwrap();
+            let mut frequencies = self.word_frequencies.write().unwrap();
+            let mut doc_lengths = self.document_lengths.write().unwrap();
+            let mut positions = self.term_positions.write().unwrap();
+            let handle = self.doc_handles.write().unwrap().intern(&doc_id);
+
+            // Terms of the version being replaced, if updating
+            let old_terms: HashSet<String> = frequencies
+                .remove(&doc_id)
+                .map(|old| old.into_keys().collect())
+                .unwrap_or_default();
+
+            let mut word_count = HashMap::new();
+            let mut doc_positions = TermPositions::new();
+            for (position, token) in tokens.iter().enumerate() {
+                *word_count.entry(token.clone()).or_insert(0) += 1;
+                doc_positions.entry(token.clone()).or_default().push(position);
+            }
+
+            // Only the posting lists of terms the update dropped or introduced
+            // change; terms in both versions keep their postings untouched.
+            // One posting per distinct term, so posting length is the document frequency
+            let mut trie = self.term_trie.write().unwrap();
+            for word in old_terms.iter().filter(|word| !word_count.contains_key(*word)) {
+                if let Some(postings) = index.get_mut(word) {
+                    postings.retain(|&h| h != handle);
+                    if postings.is_empty() {
+                        index.remove(word);
+                        trie.remove(word);
+                    }
+                }
+            }
+            for word in word_count.keys().filter(|word| !old_terms.contains(*word)) {
+                let postings = index.entry(word.clone()).or_default();
+                if postings.is_empty() {
+                    trie.insert(word);
+                    self.index_phonetic(word);
+                }
+                postings.push(handle);
+            }
+            let title_terms: HashSet<&String> = tokens.iter().take(title_token_count).collect();
+            let old_title_terms = self.title_terms(&doc_id, positions.get(&doc_id));
+            let mut title_index = self.title_index.write().unwrap();
+            Self::update_title_postings(&mut title_index, handle, old_title_terms, title_terms);
+            drop(title_index);
+
+            // Calculate TF scores
+            // Documents without tokens count as length 1 to keep BM25 length math finite
+            let doc_length = indexed_length(&tokens);
+            let previous = doc_lengths.insert(doc_id.clone(), doc_length);
+            self.adjust_avg_doc_len(doc_lengths.len(), previous, Some(doc_length));
+            positions.insert(doc_id.clone(), doc_positions);
+            self.title_token_counts.write().unwrap().insert(doc_id.clone(), title_token_count);
+            self.indexed_at.write().unwrap().insert(doc_id.clone(), self.now());
+            self.surface_terms.write().unwrap().insert(doc_id.clone(), surface_words);
+
+            let mut numeric_index = self.numeric_index.write().unwrap();
+            Self::remove_numeric_entries(&mut numeric_index, &doc_id);
+            for (key, value) in numeric_values {
+                let postings = numeric_index.entry(key).or_default();
+                let at = postings.partition_point(|(v, _)| v.total_cmp(&value).is_lt());
+                postings.insert(at, (value, doc_id.clone()));
+            }
+            
+            let mut doc_frequencies = HashMap::new();
+            for (word, count) in word_count {
+                let tf = count as f32 / doc_length as f32;
+                doc_frequencies.insert(word, tf);
+            }
+            frequencies.insert(doc_id.clone(), doc_frequencies);
+        }
+
+        self.bump_generation();
+        drop(swap_guard);
+        self.notify(change);
+        Ok(())
+    }
+
+    /// Appends `extra_text` to a document's content, indexing only the new tokens.
+    pub fn append_content(&self, doc_id: &str, extra_text: &str) -> Result<(), FerrumError> {
+        let swap_guard = self.swap_lock.read()?;
+        let mut tokens = self.tokenize_field("content", extra_text);
+        let surface_words = self.analyzer.read().unwrap().surface_words(extra_text);
+
+        // The documents lock is held until the index is updated, so a
+        // concurrent removal can't run in between and orphan the new postings
+        let mut docs = self.documents.write().unwrap();
+        let doc = docs
+            .get_mut(doc_id)
+            .ok_or_else(|| FerrumError::NotFound(doc_id.to_string()))?;
+        if !doc.content.is_empty() {
+            doc.content.push(' ');
+        }
+        doc.content.push_str(extra_text);
+
+        {
+            let mut index = self.inverted_index.write().unwrap();
+            let mut frequencies = self.word_frequencies.write().unwrap();
+            let mut doc_lengths = self.document_lengths.write().unwrap();
+            let mut positions = self.term_positions.write().unwrap();
+            let handle = self.doc_handles.write().unwrap().intern(doc_id);
+            let doc_positions = positions.entry(doc_id.to_string()).or_default();
+
+            // Positions hold every indexed token, so they give the exact
+            // length (token-less documents are stored with length 1)
+            let old_length: usize = doc_positions.values().map(Vec::len).sum();
+            if let Some(max_tokens) = self.settings.read().unwrap().max_indexed_tokens {
+                tokens.truncate(max_tokens.saturating_sub(old_length));
+            }
+            let new_length = (old_length + tokens.len()).max(1);
+
+            let mut trie = self.term_trie.write().unwrap();
+            for (offset, token) in tokens.iter().enumerate() {
+                let token_positions = doc_positions.entry(token.clone()).or_default();
+                if token_positions.is_empty() {
+                    let postings = index.entry(token.clone()).or_default();
+                    if postings.is_empty() {
+                        trie.insert(token);
+                        self.index_phonetic(token);
+                    }
+                    postings.push(handle);
+                }
+                token_positions.push(old_length + offset);
+            }
+            drop(trie);
+
+            let doc_frequencies = frequencies.entry(doc_id.to_string()).or_default();
+            for (word, word_positions) in doc_positions.iter() {
+                doc_frequencies.insert(word.clone(), word_positions.len() as f32 / new_length as f32);
+            }
+            let previous = doc_lengths.insert(doc_id.to_string(), new_length);
+            self.adjust_avg_doc_len(doc_lengths.len(), previous, Some(new_length));
+            self.indexed_at.write().unwrap().insert(doc_id.to_string(), self.now());
+            self.surface_terms.write().unwrap()
+                .entry(doc_id.to_string())
+                .or_default()
+                .extend(surface_words);
+        }
+        let change = self.record_change(ChangeEvent::Added(doc_id.to_string()), || ReplicationEvent::Upserted(doc.clone()));
+        drop(docs);
+
+        self.bump_generation();
+        drop(swap_guard);
+        self.notify(change);
+        Ok(())
+    }
+
+    pub fn remove_document(&self, doc_id: &str) -> Result<(), FerrumError> {
+        let swap_guard = self.swap_lock.read()?;
+        let change = {
+            let mut docs = self.documents.write().unwrap();
+            docs.remove(doc_id).map(|_| {
+                let mut total = self.total_documents.write().unwrap();
+                *total = total.saturating_sub(1);
+                self.record_change(ChangeEvent::Removed(doc_id.to_string()), || {
+                    ReplicationEvent::Removed(doc_id.to_string())
+                })
+            })
+        };
+
+        {
+            let mut index = self.inverted_index.write().unwrap();
+            let mut frequencies = self.word_frequencies.write().unwrap();
+            self.remove_document_from_index(doc_id, &mut index, &mut frequencies);
+            let mut doc_lengths = self.document_lengths.write().unwrap();
+            let previous = doc_lengths.remove(doc_id);
+            self.adjust_avg_doc_len(doc_lengths.len(), previous, None);
+            self.term_positions.write().unwrap().remove(doc_id);
+            self.title_token_counts.write().unwrap().remove(doc_id);
+            self.indexed_at.write().unwrap().remove(doc_id);
+            self.surface_terms.write().unwrap().remove(doc_id);
+            Self::remove_numeric_entries(&mut self.numeric_index.write().unwrap(), doc_id);
+        }
+
+        drop(swap_guard);
+        if let Some(change) = change {
+            *self.deleted_since_optimize.write().unwrap() += 1;
+            self.bump_generation();
+            self.notify(change);
+        }
+        Ok(())
+    }
+
+    /// Folds one document's length change into the running average.
+    fn adjust_avg_doc_len(&self, count: usize, old: Option<usize>, new: Option<usize>) {
+        let mut avg = self.avg_doc_len.write().unwrap();
+        *avg = match (old, new) {
+            _ if count == 0 => 0.0,
+            (None, Some(new)) => *avg + (new as f64 - *avg) / count as f64,
+            (Some(old), None) => (*avg * (count + 1) as f64 - old as f64) / count as f64,
+            (Some(old), Some(new)) => *avg + (new as f64 - old as f64) / count as f64,
+            (None, None) => *avg,
+        };
+    }
+
+    /// Indexed tokens (title first), the title's token count, and the source text.
+    fn analyze_document(&self, document: &Document) -> (Vec<String>, usize, String) {
+        let mut text = format!("{} {}", document.title, document.content);
+        let mut tokens = self.tokenize_field("title", &document.title);
+        let title_token_count = tokens.len();
+        tokens.extend(self.tokenize_field("content", &document.content));
+        for key in &self.settings.read().unwrap().searchable_metadata {
+            if let Some(value) = document.metadata.get(key) {
+                tokens.extend(self.tokenize_field("content", value));
+                text.push(' ');
+                text.push_str(value);
+            }
+        }
+        if let Some(max_tokens) = self.settings.read().unwrap().max_indexed_tokens {
+            tokens.truncate(max_tokens);
+        }
+        (tokens, title_token_count, text)
+    }
+
+    /// Rebuilds `document_lengths` and the average from the stored documents.
+    pub fn recompute_document_lengths(&self) {
+        let _swap_guard = self.swap_lock.read().unwrap();
+        let lengths: HashMap<String, usize> = self
+            .documents
+            .read()
+            .unwrap()
+            .values()
+            .map(|doc| (doc.id.clone(), indexed_length(&self.analyze_document(doc).0)))
+            .collect();
+        *self.document_lengths.write().unwrap() = lengths;
+        self.recompute_avg_doc_len();
+        self.invalidate_query_cache();
+    }
+
+    /// Recomputes the average document length exactly from
+    /// `document_lengths`, discarding float drift from incremental updates.
+    pub fn recompute_avg_doc_len(&self) {
+        let doc_lengths = self.document_lengths.read().unwrap();
+        let total: usize = doc_lengths.values().sum();
+        *self.avg_doc_len.write().unwrap() = if doc_lengths.is_empty() {
+            0.0
+        } else {
+            total as f64 / doc_lengths.len() as f64
+        };
+    }
+
+    /// Removes every document whose `timestamp` is below `cutoff_timestamp`,
+    /// returning how many were removed.
+    pub fn purge_older_than(&self, cutoff_timestamp: u64) -> Result<usize, FerrumError> {
+        let expired: Vec<String> = self
+            .documents
+            .read()?
+            .values()
+            .filter(|doc| doc.timestamp < cutoff_timestamp)
+            .map(|doc| doc.id.clone())
+            .collect();
+        for doc_id in &expired {
+            self.remove_document(doc_id)?;
+        }
+        Ok(expired.len())
+    }
+
+    /// Moves `handle`'s title postings from `old_terms` to `terms`.
+    fn update_title_postings(
+        title_index: &mut HashMap<String, Vec<DocHandle>>,
+        handle: DocHandle,
+        old_terms: Vec<String>,
+        terms: HashSet<&String>,
+    ) {
+        for term in old_terms {
+            if let Some(postings) = title_index.get_mut(&term) {
+                postings.retain(|&h| h != handle);
+                if postings.is_empty() {
+                    title_index.remove(&term);
+                }
+            }
+        }
+        for term in terms {
+            title_index.entry(term.clone()).or_default().push(handle);
+        }
+    }
+
+    /// Indexed terms that occur in `doc_id`'s title.
+    fn title_terms(&self, doc_id: &str, positions: Option<&TermPositions>) -> Vec<String> {
+        let title_len = self.title_token_counts.read().unwrap().get(doc_id).copied().unwrap_or(0);
+        positions
+            .into_iter()
+            .flatten()
+            .filter(|(_, at)| at.iter().any(|&p| p < title_len))
+            .map(|(term, _)| term.clone())
+            .collect()
+    }
+
+    fn remove_document_from_index(
+        &self,
+        doc_id: &str,
+        index: &mut HashMap<String, Vec<DocHandle>>,
+        frequencies: &mut HashMap<String, HashMap<String, f32>>,
+    ) {
+        frequencies.remove(doc_id);
+        let old_title_terms = self.title_terms(doc_id, self.term_positions.read().unwrap().get(doc_id));
+        let mut doc_handles = self.doc_handles.write().unwrap();
+        let Some(handle) = doc_handles.handle(doc_id) else {
+            return;
+        };
+        Self::update_title_postings(&mut self.title_index.write().unwrap(), handle, old_title_terms, HashSet::new());
+
+        // Remove from inverted index
+        let words_to_clean: Vec<String> = index
+            .iter()
+            .filter(|(_, docs)| docs.contains(&handle))
+            .map(|(word, _)| word.clone())
+            .collect();
+
+        for word in words_to_clean {
+            if let Some(docs) = index.get_mut(&word) {
+                docs.retain(|&h| h != handle);
+                if docs.is_empty() {
+                    index.remove(&word);
+                    self.term_trie.write().unwrap().remove(&word);
+                }
+            }
+        }
+        doc_handles.release(doc_id);
+    }
+
+    fn remove_numeric_entries(numeric_index: &mut HashMap<String, NumericPostings>, doc_id: &str) {
+        numeric_index.retain(|_, postings| {
+            postings.retain(|(_, id)| id != doc_id);
+            !postings.is_empty()
+        });
+    }
+
+    // ==================== SEARCH OPERATIONS ====================
+
+    pub fn search(&self, query: SearchQuery) -> Result<SearchResponse, FerrumError> {
+        if query.silent {
+            return self.run_search(query);
+        }
+        let text = query.query.clone();
+        let response = self.cached_search(query)?;
+        self.record_query(&text, &response);
+        let mut metrics = self.query_metrics.write().unwrap();
+        metrics.query_count += 1;
+        metrics.total_time_ms += response.query_time_ms;
+        metrics.max_time_ms = metrics.max_time_ms.max(response.query_time_ms);
+        Ok(response)
+    }
+
+    fn elapsed_ms(start_time: SystemTime) -> u64 {
+        start_time.elapsed().map(|d| d.as_millis() as u64).unwrap_or(0)
+    }
+
+    /// Latency counters over every non-silent search so far.
+    pub fn query_metrics(&self) -> QueryMetrics {
+        *self.query_metrics.read().unwrap()
+    }
+
+    fn cached_search(&self, query: SearchQuery) -> Result<SearchResponse, FerrumError> {
+        if self.query_cache.read().unwrap().is_none() {
+            return self.run_search(query);
+        }
+
+        let start_time = SystemTime::now();
+        let key = QueryCache::key(&query);
+        let generation = *self.generation.read().unwrap();
+        let hit = self.query_cache.write().unwrap().as_mut().and_then(|c| c.get(&key, generation));
+        if let Some(mut cached) = hit {
+            // Report what this lookup took, not the original search
+            cached.query_time_ms = if self.settings.read().unwrap().deterministic {
+                0
+            } else {
+                Self::elapsed_ms(start_time)
+            };
+            return Ok(cached);
+        }
+
+        let response = self.run_search(query)?;
+        if response.cancelled {
+            return Ok(response);
+        }
+        if let Some(cache) = self.query_cache.write().unwrap().as_mut() {
+            cache.insert(key, response.clone(), generation);
+        }
+        Ok(response)
+    }
+
+    /// Checks `query` for problems `search` would reject, without running it.
+    pub fn validate_query(&self, query: &SearchQuery) -> Result<(), FerrumError> {
+        let defaults = self.search_defaults.read().unwrap().clone();
+        let per_page = query.per_page.unwrap_or(defaults.per_page);
+        if query.page == Some(0) || per_page == 0 || per_page > MAX_PER_PAGE {
+            return Err(FerrumError::InvalidPagination);
+        }
+
+        let filter_keys = query.filters.iter().chain(&query.exclude_filters).flat_map(|f| f.keys());
+        let field_keys = query.has_fields.iter().chain(&query.missing_fields).flatten();
+        let sort_keys = query.sort.iter().flatten().map(|(key, _)| key);
+        let sort_by = query.sort_by.as_ref().map(|s| s.trim_start_matches('-').to_string());
+        if filter_keys.chain(field_keys).chain(sort_keys).chain(&sort_by).any(|key| key.is_empty()) {
+            return Err(FerrumError::InvalidQuery("filter and sort keys must not be empty".to_string()));
+        }
+
+        let search_fields = query.search_fields.as_ref().or(defaults.search_fields.as_ref());
+        if let Some(unknown) = search_fields.into_iter().flatten().find(|f| !ANALYZED_FIELDS.contains(&f.as_str())) {
+            return Err(FerrumError::UnknownField(unknown.clone()));
+        }
+
+        for (key, range) in query.numeric_filters.iter().flatten() {
+            if let (Some(min), Some(max)) = (range.min, range.max) {
+                if min > max {
+                    return Err(FerrumError::InvalidQuery(format!(
+                        "numeric range for '{}' has min {} above max {}",
+                        key, min, max
+                    )));
+                }
+            }
+        }
+
+        let parsed = Self::parse_search_query(query);
+        let excluded: HashSet<String> = parsed.excluded.iter().flat_map(|word| self.tokenize(word)).collect();
+        if let Some(term) = parsed.required.iter().flat_map(|word| self.tokenize(word)).find(|t| excluded.contains(t)) {
+            return Err(FerrumError::InvalidQuery(format!("'{}' is both required and excluded", term)));
+        }
+
+        if query.phonetic && self.settings.read().unwrap().phonetic.is_none() {
+            return Err(FerrumError::InvalidQuery("phonetic matching is not enabled".to_string()));
+        }
+        Ok(())
+    }
+
+    fn record_query(&self, text: &str, response: &SearchResponse) {
+        let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let capacity = *self.query_log_capacity.read().unwrap();
+        if normalized.is_empty() || capacity == 0 {
+            return;
+        }
+        let record = QueryRecord {
+            query: normalized,
+            term_count: response.query_tokens.len(),
+            hit_count: response.total_hits,
+            latency_ms: response.query_time_ms,
+            timestamp: self.current_time(),
+        };
+        let mut log = self.query_log.write().unwrap();
+        while log.len() >= capacity {
+            log.pop_front();
+        }
+        log.push_back(record);
+    }
+
+    /// The last `limit` logged searches, most recent first.
+    pub fn recent_queries(&self, limit: usize) -> Vec<QueryRecord> {
+        self.query_log.read().unwrap().iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Writes every logged search to `writer` as NDJSON, oldest first.
+    pub fn export_analytics(&self, mut writer: impl Write) -> Result<(), FerrumError> {
+        let records: Vec<QueryRecord> = self.query_log.read().unwrap().iter().cloned().collect();
+        for record in records {
+            let line = serde_json::to_string(&record).map_err(|e| FerrumError::Serialization(e.to_string()))?;
+            writeln!(writer, "{}", line).map_err(|e| FerrumError::Io(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn run_search(&self, query: SearchQuery) -> Result<SearchResponse, FerrumError> {
+        let start_time = SystemTime::now();
+        let _swap_guard = self.swap_lock.read()?;
+        self.validate_query(&query)?;
+        let mut ranked = self.rank_matches(&query);
+        if let Some(fallback) = query.fallback.filter(|f| !ranked.cancelled && ranked.scored.len() < f.min_results) {
+            self.append_fallback_matches(&query, fallback, &mut ranked);
+        }
+        Ok(self.build_response(&query, ranked, start_time))
+    }
+
+    /// Appends the matches of the relaxed `query` that `ranked` doesn't hold.
+    fn append_fallback_matches(&self, query: &SearchQuery, fallback: FallbackStrategy, ranked: &mut RankedMatches) {
+        let strict: HashSet<String> = ranked.scored.iter().map(|(doc_id, _)| doc_id.clone()).collect();
+        let mut extra = self.rank_matches(&fallback.relax(query));
+        if extra.cancelled {
+            *ranked = extra;
+            return;
+        }
+        for (doc_id, score) in extra.scored {
+            if strict.contains(&doc_id) {
+                continue;
+            }
+            if let Some(terms) = extra.matched_terms.remove(&doc_id) {
+                ranked.matched_terms.insert(doc_id.clone(), terms);
+            }
+            if let Some(scores) = extra.term_scores.remove(&doc_id) {
+                ranked.term_scores.insert(doc_id.clone(), scores);
+            }
+            ranked.fallback_ids.insert(doc_id.clone());
+            ranked.scored.push((doc_id, score));
+        }
+        for token in extra.tokens {
+            if !ranked.tokens.contains(&token) {
+                ranked.tokens.push(token);
+            }
+        }
+        ranked.truncated_expansion |= extra.truncated_expansion;
+    }
+
+    /// Runs a JSON-DSL query; see `StructuredQuery`.
+    pub fn search_structured(&self, query: StructuredQuery) -> Result<SearchResponse, FerrumError> {
+        self.search(query.into())
+    }
+
+    /// Merges the scores of every query with `combine`; response options come
+    /// from the first query.
+    pub fn search_multi(&self, queries: Vec<SearchQuery>, combine: CombineMode) -> Result<SearchResponse, FerrumError> {
+        let start_time = SystemTime::now();
+        let _swap_guard = self.swap_lock.read()?;
+        let Some(first) = queries.first() else {
+            return Err(FerrumError::InvalidQuery("search_multi needs at least one query".to_string()));
+        };
+        for query in &queries {
+            self.validate_query(query)?;
+        }
+
+        let mut merged = RankedMatches::default();
+        let mut scores: HashMap<String, f32> = HashMap::new();
+        for query in &queries {
+            let ranked = self.rank_matches(query);
+            for (doc_id, score) in ranked.scored {
+                scores
+                    .entry(doc_id)
+                    .and_modify(|total| {
+                        *total = match combine {
+                            CombineMode::Sum => *total + score,
+                            CombineMode::Max => total.max(score),
+                        }
+                    })
+                    .or_insert(score);
+            }
+            for (doc_id, terms) in ranked.matched_terms {
+                let doc_terms = merged.matched_terms.entry(doc_id).or_default();
+                for term in terms {
+                    if !doc_terms.contains(&term) {
+                        doc_terms.push(term);
+                    }
+                }
+            }
+            for token in ranked.tokens {
+                if !merged.tokens.contains(&token) {
+                    merged.tokens.push(token);
+                }
+            }
+            merged.truncated_expansion |= ranked.truncated_expansion;
+        }
+
+        merged.scored = scores.into_iter().collect();
+        if self.settings.read().unwrap().deterministic {
+            merged.scored.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        Self::sort_by_score(&mut merged.scored);
+        Ok(self.build_response(first, merged, start_time))
+    }
+
+    /// Scores, filters, sorts, and re-ranks every match; callers hold the swap guard.
+    fn rank_matches(&self, query: &SearchQuery) -> RankedMatches {
+        let defaults = self.search_defaults.read().unwrap().clone();
+        let operator = query.operator.unwrap_or(defaults.operator);
+        let search_fields = query.search_fields.clone().or(defaults.search_fields);
+        // Only a strict subset of the fields needs per-position provenance checks
+        let restricted_fields = search_fields
+            .filter(|fields| !ANALYZED_FIELDS.iter().all(|f| fields.iter().any(|s| s == f)));
+        let title_only = restricted_fields.as_ref().is_some_and(|fields| fields.iter().all(|f| f == "title"));
+        
+        let parsed = Self::parse_search_query(query);
+        let mut tokens = self.tokenize(&parsed.text);
+        // Shingles only add adjacency evidence to standard queries; phrase
+        // queries check adjacency position by position instead
+        if query.query_type != QueryType::Standard {
+            tokens.retain(|token| !is_shingle(token));
+        }
+        // Browse mode: with no usable terms but some filter, every document
+        // is a candidate at score 0 and only the filters narrow them down
+        let browse = tokens.is_empty() && Self::has_document_filters(query);
+        if tokens.is_empty() && !browse {
+            return RankedMatches::default();
+        }
+
+        let mut scores = HashMap::new();
+        let docs = self.documents.read().unwrap();
+        let only_ids: Option<HashSet<&String>> = query.only_ids.as_ref().map(|ids| ids.iter().collect());
+        if browse {
+            let candidates = docs.keys().filter(|id| only_ids.as_ref().is_none_or(|ids| ids.contains(id)));
+            scores.extend(candidates.map(|id| (id.clone(), 0.0)));
+        }
+        // Title-scoped searches expand, count document frequencies, and
+        // score against title postings alone; exclusions still use the full
+        // index. Taken in write order (full index first) to avoid deadlocks.
+        let full_index = self.inverted_index.read().unwrap();
+        let title_index = title_only.then(|| self.title_index.read().unwrap());
+        let index = title_index.as_deref().unwrap_or(&full_index);
+        let frequencies = self.word_frequencies.read().unwrap();
+        let total_docs = *self.total_documents.read().unwrap();
+
+        let doc_handles = self.doc_handles.read().unwrap();
+        let (term_groups, truncated_expansion) = self.expand_query_terms(&tokens, query, index);
+        if is_cancelled(query.cancellation.as_ref()) {
+            return RankedMatches {
+                tokens,
+                cancelled: true,
+                ..Default::default()
+            };
+        }
+
+        // Query words as typed, keyed by the term each one analyzes to, so
+        // exact surface matches can be told apart from stemmed/fuzzy ones
+        let exact_match_boost = self.settings.read().unwrap().exact_match_boost;
+        let min_term_score = self.settings.read().unwrap().min_term_score;
+        let surface_terms = self.surface_terms.read().unwrap();
+        let mut query_surfaces: HashMap<String, Vec<String>> = HashMap::new();
+        if exact_match_boost != 1.0 {
+            for word in self.analyzer.read().unwrap().surface_words(&parsed.text) {
+                for term in self.tokenize(&word) {
+                    query_surfaces.entry(term).or_default().push(word.clone());
+                }
+            }
+        }
+
+        let avg_doc_len = *self.avg_doc_len.read().unwrap() as f32;
+        let field_combine = self.settings.read().unwrap().field_combine;
+        let deterministic = self.settings.read().unwrap().deterministic;
+        let tf_saturation = self.settings.read().unwrap().tf_saturation;
+
+        // Calculate BM25 scores, tracking which query terms each document
+        // matched. With several search shards each posting list is split
+        // once by handle and every partition is scored on its own thread.
+        let query_terms: Vec<(usize, &String)> = term_groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, terms)| terms.iter().map(move |term| (group, term)))
+            .collect();
+        let postings_of = |term: &String| index.get(term).map_or(&[][..], Vec::as_slice);
+        let total_postings: usize = query_terms.iter().map(|(_, term)| postings_of(term).len()).sum();
+        let shards = {
+            let settings = self.settings.read().unwrap();
+            settings.search_shards.min(total_postings / settings.min_postings_per_shard).max(1)
+        };
+        let partitions: Vec<Vec<Vec<DocHandle>>> = if shards == 1 {
+            Vec::new()
+        } else {
+            query_terms
+                .iter()
+                .map(|(_, term)| {
+                    let mut parts = vec![Vec::new(); shards];
+                    for &handle in postings_of(term) {
+                        parts[handle as usize % shards].push(handle);
+                    }
+                    parts
+                })
+                .collect()
+        };
+        let doc_lengths = self.document_lengths.read().unwrap();
+        let cancelled = || is_cancelled(query.cancellation.as_ref());
+        let score_shard = |shard: usize| {
+            let mut partial = ShardScores::default();
+            for (k, &(group, term)) in query_terms.iter().enumerate() {
+                if cancelled() {
+                    return partial;
+                }
+                let matching_docs = postings_of(term);
+
+                let df = matching_docs.len();
+                if df == 0 { continue; }
+
+                let idf = Self::idf_for(total_docs, df);
+                let shard_docs = if shards == 1 { matching_docs } else { &partitions[k][shard][..] };
+
+                for (i, &handle) in shard_docs.iter().enumerate() {
+                    if i % CANCELLATION_CHECK_INTERVAL == 0 && cancelled() {
+                        return partial;
+                    }
+                    let doc_id = doc_handles.id(handle).to_string();
+                    if only_ids.as_ref().is_some_and(|ids| !ids.contains(&doc_id)) {
+                        continue;
+                    }
+                    if let Some(doc_freqs) = frequencies.get(&doc_id) {
+                        if let Some(&tf) = doc_freqs.get(term) {
+                            let k1 = 1.5;
+                            let b = 0.75;
+                            let doc_len = doc_lengths.get(&doc_id).copied().unwrap_or(1);
+                            let avg_doc_len = avg_doc_len.max(1.0);
+
+                            let tf = if restricted_fields.is_none() && field_combine == FieldCombine::Sum {
+                                tf
+                            } else {
+                                let (mut title, mut content) = self.field_occurrences(&doc_id, term);
+                                if let Some(fields) = &restricted_fields {
+                                    if !fields.iter().any(|f| f == "title") {
+                                        title = 0;
+                                    }
+                                    if !fields.iter().any(|f| f == "content") {
+                                        content = 0;
+                                    }
+                                }
+                                if title + content == 0 {
+                                    continue;
+                                }
+                                field_combine.combine(title, content) / doc_len as f32
+                            };
+
+                            let bm25_tf = tf_saturation.saturate(tf, doc_len, avg_doc_len, k1, b);
+
+                            let mut score = idf * bm25_tf;
+                            if let Some(floor) = min_term_score {
+                                score = score.max(floor);
+                            }
+
+                            let exact = query_surfaces.get(term).is_some_and(|words| {
+                                surface_terms
+                                    .get(&doc_id)
+                                    .is_some_and(|doc_words| words.iter().any(|w| doc_words.contains(w)))
+                            });
+                            if exact {
+                                score += score.abs() * (exact_match_boost - 1.0);
+                            }
+
+                            *partial.scores.entry(doc_id.clone()).or_insert(0.0) += score;
+                            if query.debug_scores {
+                                *partial.term_scores.entry(doc_id.clone()).or_default().entry(term.clone()).or_insert(0.0) += score;
+                            }
+                            partial.matched_groups.entry(doc_id.clone()).or_default().insert(group);
+                            let doc_terms = partial.matched_terms.entry(doc_id.clone()).or_default();
+                            if !doc_terms.contains(term) {
+                                doc_terms.push(term.clone());
+                            }
+                        }
+                    }
+                }
+            }
+            partial
+        };
+        let partials: Vec<ShardScores> = if shards == 1 {
+            vec![score_shard(0)]
+        } else {
+            let score_shard = &score_shard;
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..shards).map(|shard| scope.spawn(move || score_shard(shard))).collect();
+                workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+            })
+        };
+        let mut matched_groups: HashMap<String, HashSet<usize>> = HashMap::new();
+        let mut matched_terms: HashMap<String, Vec<String>> = HashMap::new();
+        let mut term_scores: HashMap<String, HashMap<String, f32>> = HashMap::new();
+        // Shards hold disjoint documents, so merging never combines entries
+        for partial in partials {
+            scores.extend(partial.scores);
+            matched_groups.extend(partial.matched_groups);
+            matched_terms.extend(partial.matched_terms);
+            term_scores.extend(partial.term_scores);
+        }
+        if cancelled() {
+            return RankedMatches {
+                tokens,
+                cancelled: true,
+                ..Default::default()
+            };
+        }
+
+        // Shingle groups add score but don't count as query words
+        let word_groups: Vec<Vec<String>> = term_groups
+            .iter()
+            .zip(&tokens)
+            .filter(|(_, token)| !is_shingle(token))
+            .map(|(group, _)| group.clone())
+            .collect();
+        let matched_words = |doc_id: &String| {
+            matched_groups
+                .get(doc_id)
+                .map_or(0, |groups| groups.iter().filter(|&&g| !is_shingle(&tokens[g])).count())
+        };
+
+        let minimum_should_match = match (query.minimum_should_match, operator) {
+            (Some(minimum), _) => Some(minimum),
+            (None, QueryOperator::And) => Some(MinimumShouldMatch::Percentage(100.0)),
+            (None, QueryOperator::Or) => None,
+        };
+        if let Some(minimum) = minimum_should_match {
+            let required = minimum.required(word_groups.len());
+            scores.retain(|doc_id, _| matched_words(doc_id) >= required);
+        }
+
+        // Phrase queries only keep documents where the terms appear consecutively
+        if query.query_type != QueryType::Standard && !browse {
+            let positions = self.term_positions.read().unwrap();
+            scores.retain(|doc_id, _| {
+                positions
+                    .get(doc_id)
+                    .is_some_and(|doc_positions| Self::contains_phrase(doc_positions, &term_groups))
+            });
+        }
+
+        // Keep only documents matching every `+term` (or one of its expansions)
+        let required_groups: Vec<usize> = parsed
+            .required
+            .iter()
+            .flat_map(|word| self.tokenize(word))
+            .filter_map(|term| tokens.iter().position(|t| *t == term))
+            .collect();
+        if !required_groups.is_empty() {
+            scores.retain(|doc_id, _| {
+                matched_groups
+                    .get(doc_id)
+                    .is_some_and(|groups| required_groups.iter().all(|g| groups.contains(g)))
+            });
+        }
+
+        // Drop documents containing any `-term` exclusion
+        let excluded_docs: HashSet<&str> = parsed
+            .excluded
+            .iter()
+            .flat_map(|word| self.tokenize(word))
+            .filter_map(|term| full_index.get(&term))
+            .flatten()
+            .map(|&handle| doc_handles.id(handle))
+            .collect();
+        scores.retain(|doc_id, _| !excluded_docs.contains(doc_id.as_str()));
+
+        // Reward documents where the query terms sit close together
+        let proximity_boost = self.settings.read().unwrap().proximity_boost;
+        if proximity_boost > 0.0 && word_groups.len() > 1 {
+            let positions = self.term_positions.read().unwrap();
+            for (doc_id, score) in scores.iter_mut() {
+                let distance = positions
+                    .get(doc_id)
+                    .and_then(|doc_positions| Self::min_term_distance(doc_positions, &word_groups));
+                if let Some(distance) = distance {
+                    *score += proximity_boost / distance as f32;
+                }
+            }
+        }
+
+        // Reward documents covering every query term
+        if let Some(bonus) = self.settings.read().unwrap().full_match_bonus.filter(|_| !browse) {
+            for (doc_id, score) in scores.iter_mut() {
+                if matched_words(doc_id) == word_groups.len() {
+                    *score += bonus;
+                }
+            }
+        }
+
+        // Navigational queries: the document titled exactly like the query leads
+        if let Some(boost) = self.settings.read().unwrap().exact_title_boost.filter(|_| !browse) {
+            let normalize = |text: &str| {
+                text.split_whitespace().map(|word| word.trim_matches('"')).collect::<Vec<_>>().join(" ").to_lowercase()
+            };
+            let wanted = normalize(&parsed.text);
+            for (doc_id, score) in scores.iter_mut() {
+                if docs.get(doc_id).is_some_and(|doc| normalize(&doc.title) == wanted) {
+                    *score += boost;
+                }
+            }
+        }
+
+        // Apply metadata filters and access control before anything is counted
+        scores.retain(|doc_id, _| docs.get(doc_id).is_some_and(|doc| Self::passes_filters(query, doc)));
+
+        // Scale scores of documents matching a boost filter set; like the exact
+        // match boost, this stays sign-safe for negative-IDF scores
+        if let Some(boosts) = &query.boost_filters {
+            for (doc_id, score) in scores.iter_mut() {
+                let Some(doc) = docs.get(doc_id) else { continue };
+                for (conditions, factor) in boosts {
+                    if conditions.iter().all(|(key, value)| doc.metadata.get(key) == Some(value)) {
+                        *score += score.abs() * (factor - 1.0);
+                    }
+                }
+            }
+        }
+
+        // Apply numeric range filters through the sorted numeric index
+        if let Some(ranges) = &query.numeric_filters {
+            let numeric_index = self.numeric_index.read().unwrap();
+            for (key, range) in ranges {
+                let allowed: HashSet<&String> = numeric_index
+                    .get(key)
+                    .map(|postings| range.matching(postings).collect())
+                    .unwrap_or_default();
+                scores.retain(|doc_id, _| allowed.contains(doc_id));
+            }
+        }
+
+        // With a hit cap and relevance order, only the top `cap` matches are
+        // ever returned, so collect them through a heap bounded at `cap`
+        let relevance_order = !browse && query.sort.is_none() && query.sort_by.is_none();
+        let heap_cap = query
+            .track_total_hits
+            .filter(|&cap| relevance_order && scores.len() > cap && self.reranker.read().unwrap().is_none());
+        let hits_capped = heap_cap.is_some();
+        let mut sorted_results: Vec<_> = match heap_cap {
+            Some(cap) => {
+                let mut top = BinaryHeap::with_capacity(cap);
+                for (doc_id, score) in scores {
+                    let hit = RankedHit(doc_id, score);
+                    if top.len() < cap {
+                        top.push(hit);
+                    } else if let Some(mut worst) = top.peek_mut() {
+                        if hit < *worst {
+                            *worst = hit;
+                        }
+                    }
+                }
+                top.into_iter().map(|RankedHit(doc_id, score)| (doc_id, score)).collect()
+            }
+            None => scores.into_iter().collect(),
+        };
+
+        if deterministic {
+            sorted_results.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        Self::sort_by_score(&mut sorted_results);
+
+        // Unscored browse results default to indexing order
+        if browse {
+            let indexed_at = self.indexed_at.read().unwrap();
+            sorted_results.sort_by_key(|(id, _)| (indexed_at.get(id).copied(), id.clone()));
+        }
+
+        // `sort` keys, or `sort_by: "key"` (ascending) / `"-key"` (descending),
+        // override relevance order; numeric metadata wins over string metadata,
+        // missing keys go last, and ties keep relevance order
+        let sort_keys: Vec<(&str, SortOrder)> = match (&query.sort, &query.sort_by) {
+            (Some(keys), _) => keys.iter().map(|(key, order)| (key.as_str(), *order)).collect(),
+            (None, Some(sort_by)) => match sort_by.strip_prefix('-') {
+                Some(key) => vec![(key, SortOrder::Desc)],
+                None => vec![(sort_by.as_str(), SortOrder::Asc)],
+            },
+            (None, None) => vec![],
+        };
+        if !sort_keys.is_empty() {
+            sorted_results.sort_by(|a, b| {
+                let (a, b) = (docs.get(&a.0), docs.get(&b.0));
+                sort_keys
+                    .iter()
+                    .map(|&(key, order)| {
+                        let a = a.and_then(|doc| FieldValue::of(doc, key));
+                        let b = b.and_then(|doc| FieldValue::of(doc, key));
+                        match (a, b) {
+                            (Some(a), Some(b)) if order == SortOrder::Desc => b.cmp(&a),
+                            (Some(a), Some(b)) => a.cmp(&b),
+                            (Some(_), None) => std::cmp::Ordering::Less,
+                            (None, Some(_)) => std::cmp::Ordering::Greater,
+                            (None, None) => std::cmp::Ordering::Equal,
+                        }
+                    })
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+
+        // Let the re-ranking hook reorder the head of the candidate list
+        if let Some((top_k, rerank)) = self.reranker.read().unwrap().as_ref() {
+            let k = std::cmp::min(*top_k, sorted_results.len());
+            let mut candidates: Vec<SearchResult> = sorted_results[..k]
+                .iter()
+                .filter_map(|(doc_id, score)| {
+                    docs.get(doc_id).map(|doc| {
+                        let terms = matched_terms.get(doc_id).cloned().unwrap_or_default();
+                        self.build_result(doc, *score, vec![], terms, query.result_content_len)
+                    })
+                })
+                .collect();
+
+            rerank(&mut candidates);
+
+            let reranked = candidates.into_iter().map(|result| (result.id, result.score));
+            sorted_results.splice(..k, reranked);
+        }
+
+        RankedMatches {
+            scored: sorted_results,
+            matched_terms,
+            term_scores,
+            tokens,
+            truncated_expansion,
+            fallback_ids: HashSet::new(),
+            cancelled: false,
+            hits_capped,
+        }
+    }
+
+    /// Counts, paginates, and highlights ranked matches into a response.
+    fn build_response(&self, query: &SearchQuery, ranked: RankedMatches, start_time: SystemTime) -> SearchResponse {
+        let RankedMatches {
+            scored: mut sorted_results,
+            matched_terms,
+            term_scores,
+            tokens,
+            truncated_expansion,
+            fallback_ids,
+            cancelled,
+            mut hits_capped,
+        } = ranked;
+        if let Some(limit) = query.limit {
+            sorted_results.truncate(limit);
+        }
+        // Counting, statistics, and pagination all work on the capped list
+        if let Some(cap) = query.track_total_hits.filter(|&cap| sorted_results.len() > cap) {
+            sorted_results.truncate(cap);
+            hits_capped = true;
+        }
+        let defaults = self.search_defaults.read().unwrap().clone();
+        let per_page = query.per_page.unwrap_or(defaults.per_page);
+        let highlight = query.highlight.unwrap_or(defaults.highlight);
+        let deterministic = self.settings.read().unwrap().deterministic;
+        let docs = self.documents.read().unwrap();
+
+        let matched = sorted_results.len();
+        let total_hits = matched;
+        let total_hits_relation = if hits_capped {
+            Relation::GreaterThanOrEqual
+        } else {
+            Relation::Equal
+        };
+        let buckets = match &query.bucket_scores {
+            Some(thresholds) => Self::bucket_counts(&sorted_results, thresholds),
+            None => vec![],
+        };
+        let score_stats = if query.include_score_stats {
+            let scores: Vec<f32> = sorted_results.iter().map(|(_, score)| *score).collect();
+            ScoreStats::from_scores(&scores)
+        } else {
+            None
+        };
+        let page = query.page.unwrap_or(1);
+        let total_pages = total_hits.div_ceil(per_page);
+        let out_of_range = page > total_pages.max(1);
+        let next_page = (page < total_pages).then_some(page + 1);
+        let prev_page = (page > 1).then(|| (page - 1).min(total_pages.max(1)));
+
+        // Pagination
+        let start = (page - 1) * per_page;
+        let end = std::cmp::min(start + per_page, matched);
+        
+        let mut results = Vec::new();
+        for (doc_id, score) in sorted_results.iter().skip(start).take(end.saturating_sub(start)) {
+            if let Some(doc) = docs.get(doc_id) {
+                let highlights = if highlight && query.best_snippet_only {
+                    self.best_snippet(doc, &tokens).into_iter().collect()
+                } else if highlight {
+                    self.generate_highlights(doc, &tokens, query.highlight_mode, query.highlight_config.max_snippets)
+                } else {
+                    vec![]
+                };
+
+                let terms = matched_terms.get(doc_id).cloned().unwrap_or_default();
+                let mut result = self.build_result(doc, *score, highlights, terms, query.result_content_len);
+                if query.debug_scores {
+                    result.term_scores = term_scores.get(doc_id).cloned().unwrap_or_default();
+                }
+                result.fallback = fallback_ids.contains(doc_id);
+                if highlight {
+                    result.field_highlights = self.generate_field_highlights(doc, &tokens, &query.highlight_config);
+                    if let HighlightOutput::Joined(separator) = &query.highlight_output {
+                        result.joined_highlight = Some(result.highlights.join(separator));
+                    }
+                }
+                results.push(result);
+            }
+        }
+
+        let mut field_groups: HashMap<String, Vec<SearchResult>> = HashMap::new();
+        if query.group_by_field {
+            for result in &results {
+                let in_title = matched_terms
+                    .get(&result.id)
+                    .into_iter()
+                    .flatten()
+                    .any(|term| self.field_occurrences(&result.id, term).0 > 0);
+                let field = if in_title { "title" } else { "content" };
+                field_groups.entry(field.to_string()).or_default().push(result.clone());
+            }
+        }
+
+        let query_time_ms = if deterministic { 0 } else { Self::elapsed_ms(start_time) };
+
+        SearchResponse {
+            results,
+            total_hits,
+            total_hits_relation,
+            query_time_ms,
+            page,
+            per_page,
+            total_pages,
+            has_next: next_page.is_some(),
+            has_prev: prev_page.is_some(),
+            out_of_range,
+            next_page,
+            prev_page,
+            truncated_expansion,
+            buckets,
+            query_tokens: tokens,
+            score_stats,
+            index_generation: *self.generation.read().unwrap(),
+            field_groups,
+            cancelled,
+        }
+    }
+
+    /// Expands each query token into the indexed terms it may match, and
+    /// reports whether any expansion hit the `max_expansions` cap.
+    fn expand_query_terms(
+        &self,
+        tokens: &[String],
+        query: &SearchQuery,
+        index: &HashMap<String, Vec<DocHandle>>,
+    ) -> (Vec<Vec<String>>, bool) {
+        let last = tokens.len().saturating_sub(1);
+        let cancellation = query.cancellation.as_ref();
+        let mut truncated_expansion = false;
+        // A cancelled search stops expanding; remaining groups stay empty
+        let term_groups = tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| {
+                let (terms, truncated) = if is_cancelled(cancellation) {
+                    (Vec::new(), false)
+                } else if query.query_type == QueryType::PhrasePrefix && i == last {
+                    self.prefix_expand_token(token, index, cancellation)
+                } else if query.phonetic {
+                    self.phonetic_expand_token(token, index)
+                } else if query.fuzzy {
+                    self.fuzzy_expand_token(token, index, cancellation)
+                } else {
+                    (vec![token.clone()], false)
+                };
+                truncated_expansion |= truncated;
+                terms
+            })
+            .collect();
+        (term_groups, truncated_expansion)
+    }
+
+    /// Whether `search` would return anything for `query`, stopping at the
+    /// first passing candidate without scoring.
+    pub fn has_match(&self, query: SearchQuery) -> Result<bool, FerrumError> {
+        let _swap_guard = self.swap_lock.read()?;
+        self.validate_query(&query)?;
+        if query.limit == Some(0) {
+            return Ok(false);
+        }
+        if self.find_match(&query) {
+            return Ok(true);
+        }
+        // `search` relaxes a query matching too few documents, so one that
+        // matched none may still be answered by its fallback
+        Ok(query
+            .fallback
+            .is_some_and(|fallback| fallback.min_results > 0 && self.find_match(&fallback.relax(&query))))
+    }
+
+    /// `has_match` for a validated query, ignoring its fallback.
+    fn find_match(&self, query: &SearchQuery) -> bool {
+        let restricted = query.search_fields.is_some() || self.search_defaults.read().unwrap().search_fields.is_some();
+        if restricted || self.reranker.read().unwrap().is_some() {
+            return !self.rank_matches(query).scored.is_empty();
+        }
+
+        let parsed = Self::parse_search_query(query);
+        let mut tokens = self.tokenize(&parsed.text);
+        if query.query_type != QueryType::Standard {
+            tokens.retain(|token| !is_shingle(token));
+        }
+        if tokens.is_empty() && !Self::has_document_filters(query) {
+            return false;
+        }
+        let docs = self.documents.read().unwrap();
+        let index = self.inverted_index.read().unwrap();
+        let frequencies = self.word_frequencies.read().unwrap();
+        let positions = self.term_positions.read().unwrap();
+        let doc_handles = self.doc_handles.read().unwrap();
+        let (term_groups, _) = self.expand_query_terms(&tokens, query, &index);
+        let word_groups: Vec<&Vec<String>> = term_groups
+            .iter()
+            .zip(&tokens)
+            .filter(|(_, token)| !is_shingle(token))
+            .map(|(group, _)| group)
+            .collect();
+
+        let operator = query.operator.unwrap_or(self.search_defaults.read().unwrap().operator);
+        let minimum = match (query.minimum_should_match, operator) {
+            (Some(minimum), _) => minimum.required(word_groups.len()),
+            (None, QueryOperator::And) => word_groups.len(),
+            (None, QueryOperator::Or) => 0,
+        };
+        let required_groups: Vec<usize> = parsed
+            .required
+            .iter()
+            .flat_map(|word| self.tokenize(word))
+            .filter_map(|term| tokens.iter().position(|t| *t == term))
+            .collect();
+        let excluded: Vec<String> = parsed.excluded.iter().flat_map(|word| self.tokenize(word)).collect();
+
+        let passes = |doc_id: &str, doc: &Document| -> bool {
+            query.only_ids.as_ref().is_none_or(|ids| ids.iter().any(|id| id == doc_id))
+                && Self::passes_filters(query, doc)
+                && query.numeric_filters.iter().flatten().all(|(key, range)| {
+                    doc.numeric_metadata.get(key).is_some_and(|&value| range.contains(value))
+                })
+                && !excluded
+                    .iter()
+                    .any(|term| frequencies.get(doc_id).is_some_and(|doc_terms| doc_terms.contains_key(term)))
+        };
+
+        // Browse mode, as in `search`: any document passing the filters
+        if tokens.is_empty() {
+            return docs.iter().any(|(doc_id, doc)| passes(doc_id, doc));
+        }
+
+        let matches = |doc_id: &str| -> bool {
+            let (Some(doc), Some(doc_terms)) = (docs.get(doc_id), frequencies.get(doc_id)) else {
+                return false;
+            };
+            let in_group = |group: &Vec<String>| group.iter().any(|term| doc_terms.contains_key(term));
+            let matched = word_groups.iter().filter(|group| in_group(group)).count();
+            matched >= minimum.max(1)
+                && required_groups.iter().all(|&g| in_group(&term_groups[g]))
+                && (query.query_type == QueryType::Standard
+                    || positions.get(doc_id).is_some_and(|p| Self::contains_phrase(p, &term_groups)))
+                && passes(doc_id, doc)
+        };
+
+        let mut seen = HashSet::new();
+        term_groups
+            .iter()
+            .flatten()
+            .filter_map(|term| index.get(term))
+            .flatten()
+            .filter(|&&handle| seen.insert(handle))
+            .any(|&handle| matches(doc_handles.id(handle)))
+    }
+
+    /// Whether `query` has filters that let a query without terms browse.
+    fn has_document_filters(query: &SearchQuery) -> bool {
+        query.filters.is_some()
+            || query.exclude_filters.is_some()
+            || query.has_fields.is_some()
+            || query.missing_fields.is_some()
+            || query.numeric_filters.is_some()
+    }
+
+    /// The metadata and ACL conditions of `query`; numeric ranges are checked separately.
+    fn passes_filters(query: &SearchQuery, doc: &Document) -> bool {
+        query.filters.iter().flatten().all(|(key, value)| doc.metadata.get(key) == Some(value))
+            && !query.exclude_filters.iter().flatten().any(|(key, value)| doc.metadata.get(key) == Some(value))
+            && query.has_fields.iter().flatten().all(|key| doc.metadata.contains_key(key))
+            && !query.missing_fields.iter().flatten().any(|key| doc.metadata.contains_key(key))
+            && Self::acl_allows(query.allowed_acls.as_deref(), doc)
+    }
+
+    /// Whether a caller in `allowed` groups (`None`: unrestricted) may see `doc`.
+    fn acl_allows(allowed: Option<&[String]>, doc: &Document) -> bool {
+        allowed.is_none_or(|allowed| doc.acl.is_empty() || doc.acl.iter().any(|group| allowed.contains(group)))
+    }
+
+    /// The IDF `search` gives an indexed term; 0 if it isn't indexed.
+    pub fn idf(&self, term: &str) -> f32 {
+        let _swap_guard = self.swap_lock.read().unwrap();
+        let df = self.inverted_index.read().unwrap().get(term).map_or(0, Vec::len);
+        if df == 0 {
+            return 0.0;
+        }
+        Self::idf_for(*self.total_documents.read().unwrap(), df)
+    }
+
+    /// Indexed terms sharing documents with `term` (already analyzed), with
+    /// how many of its documents each appears in, most frequent first.
+    pub fn co_occurring_terms(&self, term: &str, limit: usize) -> Vec<(String, usize)> {
+        let _swap_guard = self.swap_lock.read().unwrap();
+        let index = self.inverted_index.read().unwrap();
+        let frequencies = self.word_frequencies.read().unwrap();
+        let doc_handles = self.doc_handles.read().unwrap();
+
+        let mut counts: HashMap<&String, usize> = HashMap::new();
+        for &handle in index.get(term).into_iter().flatten() {
+            let Some(doc_freqs) = frequencies.get(doc_handles.id(handle)) else {
+                continue;
+            };
+            for other in doc_freqs.keys().filter(|other| *other != term) {
+                *counts.entry(other).or_insert(0) += 1;
+            }
+        }
+
+        let mut ranked: Vec<(String, usize)> = counts.into_iter().map(|(t, n)| (t.clone(), n)).collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ranked.truncate(limit);
+        ranked
+    }
+
+    /// Stable descending sort by score; NaN scores sort last and become 0.
+    fn sort_by_score(scored: &mut [(String, f32)]) {
+        scored.sort_by(|a, b| Self::score_order(a.1, b.1));
+        for (_, score) in scored.iter_mut().filter(|(_, score)| score.is_nan()) {
+            *score = 0.0;
+        }
+    }
+
+    /// Descending score order with NaN last.
+    fn score_order(a: f32, b: f32) -> std::cmp::Ordering {
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => std::cmp::Ordering::Equal,
+            (true, false) => std::cmp::Ordering::Greater,
+            (false, true) => std::cmp::Ordering::Less,
+            (false, false) => b.total_cmp(&a),
+        }
+    }
+
+    fn idf_for(total_docs: usize, df: usize) -> f32 {
+        ((total_docs as f32 - df as f32 + 0.5) / (df as f32 + 0.5)).ln()
+    }
+
+    /// Estimates how much work `search` would do for `query` by expanding its
+    /// terms and counting candidates, without scoring anything.
+    pub fn estimate_cost(&self, query: &SearchQuery) -> QueryCost {
+        let _swap_guard = self.swap_lock.read().unwrap();
+        let parsed = Self::parse_search_query(query);
+        let tokens = self.tokenize(&parsed.text);
+        let search_fields = query.search_fields.clone().or(self.search_defaults.read().unwrap().search_fields.clone());
+        let index = if search_fields.is_some_and(|fields| !fields.is_empty() && fields.iter().all(|f| f == "title")) {
+            self.title_index.read().unwrap()
+        } else {
+            self.inverted_index.read().unwrap()
+        };
+
+        let (term_groups, _) = self.expand_query_terms(&tokens, query, &index);
+        let terms: HashSet<&String> = term_groups.iter().flatten().collect();
+        let candidates: HashSet<&DocHandle> = terms
+            .iter()
+            .filter_map(|term| index.get(*term))
+            .flatten()
+            .collect();
+
+        QueryCost {
+            candidate_docs: candidates.len(),
+            expanded_terms: terms.len(),
+        }
+    }
+
+    /// `(title, content)` occurrence counts of `term` in a document.
+    fn field_occurrences(&self, doc_id: &str, term: &str) -> (usize, usize) {
+        let title_len = self.title_token_counts.read().unwrap().get(doc_id).copied().unwrap_or(0);
+        self.term_positions
+            .read()
+            .unwrap()
+            .get(doc_id)
+            .and_then(|doc_positions| doc_positions.get(term))
+            .map_or((0, 0), |offsets| {
+                let title = offsets.iter().filter(|&&offset| offset < title_len).count();
+                (title, offsets.len() - title)
+            })
+    }
+
+    fn bucket_counts(scored: &[(String, f32)], thresholds: &[f32]) -> Vec<(f32, f32, usize)> {
+        let mut bounds = vec![f32::NEG_INFINITY];
+        bounds.extend(thresholds.iter().copied());
+        bounds.push(f32::INFINITY);
+        bounds.sort_by(|a, b| a.total_cmp(b));
+
+        bounds
+            .windows(2)
+            .map(|pair| {
+                let count = scored
+                    .iter()
+                    .filter(|(_, score)| *score >= pair[0] && *score < pair[1])
+                    .count();
+                (pair[0], pair[1], count)
+            })
+            .collect()
+    }
+
+    // ==================== AUTOCOMPLETE & SUGGESTIONS ====================
+
+    /// Indexed terms starting with `prefix`, most frequent first.
+    pub fn autocomplete(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let _swap_guard = self.swap_lock.read().unwrap();
+        let index = self.inverted_index.read().unwrap();
+        let trie = self.term_trie.read().unwrap();
+        Self::ranked_completions(&trie, &index, prefix, limit)
+    }
+
+    /// Completions for several prefixes under a single acquisition of the
+    /// index locks, keyed by prefix as given.
+    pub fn autocomplete_batch(&self, prefixes: &[&str], limit: usize) -> HashMap<String, Vec<String>> {
+        let _swap_guard = self.swap_lock.read().unwrap();
+        let index = self.inverted_index.read().unwrap();
+        let trie = self.term_trie.read().unwrap();
+        prefixes
+            .iter()
+            .map(|prefix| (prefix.to_string(), Self::ranked_completions(&trie, &index, prefix, limit)))
+            .collect()
+    }
+
+    /// Indexed terms starting with `prefix`, most frequent first.
+    fn ranked_completions(
+        trie: &TermTrie,
+        index: &HashMap<String, Vec<DocHandle>>,
+        prefix: &str,
+        limit: usize,
+    ) -> Vec<String> {
+        let mut suggestions = trie.completions(&prefix.to_lowercase());
+        // Alphabetical among equal frequencies, so the list is stable across runs
+        suggestions.sort_by_cached_key(|word| (Reverse(index.get(word).map_or(0, Vec::len)), word.clone()));
+        suggestions.truncate(limit);
+        suggestions
+    }
+
+    /// Past queries starting with `prefix` (case-insensitive), most frequent
+    /// first; ties go to the most recently submitted.
+    pub fn popular_queries(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let prefix = prefix.to_lowercase();
+        let log = self.query_log.read().unwrap();
+        let mut stats: HashMap<&str, (usize, usize)> = HashMap::new();
+        for (position, record) in log.iter().enumerate() {
+            if record.query.starts_with(&prefix) {
+                let entry = stats.entry(record.query.as_str()).or_insert((0, 0));
+                entry.0 += 1;
+                entry.1 = position;
+            }
+        }
+
+        let mut ranked: Vec<(&str, (usize, usize))> = stats.into_iter().collect();
+        ranked.sort_by_key(|&(_, (count, last_seen))| Reverse((count, last_seen)));
+        ranked.into_iter().take(limit).map(|(query, _)| query.to_string()).collect()
+    }
+
+    pub fn suggest(&self, query: &str) -> Vec<String> {
+        let tokens = self.tokenize(query);
+        let index = self.inverted_index.read().unwrap();
+        
+        let mut suggestions = Vec::new();
+        for token in tokens {
+            let fuzzy_matches = self.fuzzy_search_token(&token, &index);
+            for doc_id in fuzzy_matches.iter().take(3) {
+                if let Some(doc) = self.documents.read().unwrap().get(doc_id) {
+                    suggestions.push(doc.title.clone());
+                }
+            }
+        }
+        
+        suggestions.truncate(5);
+        suggestions
+    }
+
+    /// The `limit` document titles closest to the whole input, hiding those
+    /// `allowed_acls` excludes.
+    pub fn suggest_titles(&self, query: &str, limit: usize, allowed_acls: Option<&[String]>) -> Vec<String> {
+        let normalize = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let query = normalize(query);
+        if query.is_empty() {
+            return vec![];
+        }
+        let query_len = query.chars().count() as f32;
+
+        let docs = self.documents.read().unwrap();
+        let titles: HashSet<&String> = docs
+            .values()
+            .filter(|doc| Self::acl_allows(allowed_acls, doc))
+            .map(|doc| &doc.title)
+            .collect();
+        let mut ranked: Vec<(f32, usize, &String)> = titles
+            .into_iter()
+            .map(|title| {
+                let normalized = normalize(title);
+                let partial = self.substring_edit_distance(&query, &normalized) as f32 / query_len;
+                (partial, self.edit_distance(&query, &normalized), title)
+            })
+            .collect();
+        ranked.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(b.2)));
+        ranked.into_iter().take(limit).map(|(_, _, title)| title.clone()).collect()
+    }
+
+    // ==================== UTILITY METHODS ====================
+
+    /// Analyzes query text with every field's pipeline.
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        let mut tokens = self.tokenize_field("content", text);
+        if self.field_analyzers.read().unwrap().contains_key("title") {
+            for token in self.tokenize_field("title", text) {
+                if !tokens.contains(&token) {
+                    tokens.push(token);
+                }
+            }
+        }
+        tokens
+    }
+
+    fn tokenize_field(&self, field: &str, text: &str) -> Vec<String> {
+        let mut tokens = match self.field_analyzers.read().unwrap().get(field) {
+            Some(analyzer) => analyzer.analyze(text),
+            None => self.analyzer.read().unwrap().analyze(text),
+        };
+        let stop_words = self.stop_words.read().unwrap();
+        if !stop_words.is_empty() {
+            tokens.retain(|token| !stop_words.contains(token));
+        }
+        tokens
+    }
+
+    /// The query's clauses: `bool_query` as given, else parsed from `query`.
+    fn parse_search_query(query: &SearchQuery) -> ParsedQuery {
+        let Some(clauses) = &query.bool_query else {
+            return Self::parse_query(&query.query);
+        };
+        let words = |clauses: &[String]| -> Vec<String> {
+            clauses.iter().flat_map(|clause| clause.split_whitespace()).map(str::to_string).collect()
+        };
+        let required = words(&clauses.must);
+        let mut text = required.clone();
+        text.extend(words(&clauses.should));
+        ParsedQuery {
+            text: text.join(" "),
+            excluded: words(&clauses.must_not),
+            required,
+        }
+    }
+
+    /// Separates `-word` exclusions from the rest of the query string.
+    fn parse_query(query: &str) -> ParsedQuery {
+        let mut words = Vec::new();
+        let mut excluded = Vec::new();
+        let mut required = Vec::new();
+        for word in query.split_whitespace() {
+            if let Some(negated) = word.strip_prefix('-').filter(|w| !w.is_empty()) {
+                excluded.push(negated.to_string());
+            } else if let Some(must) = word.strip_prefix('+').filter(|w| !w.is_empty()) {
+                required.push(must.to_string());
+                words.push(must);
+            } else {
+                words.push(word);
+            }
+        }
+
+        ParsedQuery {
+            text: words.join(" "),
+            excluded,
+            required,
+        }
+    }
+
+    fn fuzzy_search_token(&self, token: &str, index: &HashMap<String, Vec<DocHandle>>) -> Vec<String> {
+        let mut matches = Vec::new();
+
+        let doc_handles = self.doc_handles.read().unwrap();
+        let (terms, _) = self.fuzzy_expand_token(token, index, None);
+        for term in terms {
+            if let Some(docs) = index.get(&term) {
+                matches.extend(docs.iter().map(|&handle| doc_handles.id(handle).to_string()));
+            }
+        }
+
+        matches.sort();
+        matches.dedup();
+        matches
+    }
+
+    /// Indexed terms within edit distance 1 of `token`, and whether the list was cut.
+    fn fuzzy_expand_token(
+        &self,
+        token: &str,
+        index: &HashMap<String, Vec<DocHandle>>,
+        cancellation: Option<&CancellationToken>,
+    ) -> (Vec<String>, bool) {
+        let max_expansions = self.settings.read().unwrap().max_expansions;
+
+        // Fuzzy matches (edit distance = 1)
+        let neighbours: Vec<String> = Self::scan_vocabulary(index, cancellation)
+            .filter(|word| *word != token && self.edit_distance(token, word) <= 1)
+            .cloned()
+            .collect();
+
+        // Exact match first
+        let mut terms = Vec::new();
+        let mut budget = max_expansions;
+        if index.contains_key(token) && budget > 0 {
+            terms.push(token.to_string());
+            budget -= 1;
+        }
+
+        let (neighbours, truncated) = Self::cap_expansions(neighbours, index, budget);
+        terms.extend(neighbours);
+        (terms, truncated)
+    }
+
+    /// The token itself plus every indexed term sharing its phonetic code.
+    fn phonetic_expand_token(&self, token: &str, index: &HashMap<String, Vec<DocHandle>>) -> (Vec<String>, bool) {
+        let max_expansions = self.settings.read().unwrap().max_expansions;
+        let algorithm = self.settings.read().unwrap().phonetic;
+        let sound_alikes: Vec<String> = algorithm
+            .and_then(|algorithm| algorithm.encode(token))
+            .and_then(|code| self.phonetic_index.read().unwrap().get(&code).cloned())
+            .into_iter()
+            .flatten()
+            .filter(|term| term != token && index.get(term).is_some_and(|postings| !postings.is_empty()))
+            .collect();
+
+        let mut terms = Vec::new();
+        let mut budget = max_expansions;
+        if index.contains_key(token) && budget > 0 {
+            terms.push(token.to_string());
+            budget -= 1;
+        }
+
+        let (sound_alikes, truncated) = Self::cap_expansions(sound_alikes, index, budget);
+        terms.extend(sound_alikes);
+        (terms, truncated)
+    }
+
+    fn prefix_expand_token(
+        &self,
+        prefix: &str,
+        index: &HashMap<String, Vec<DocHandle>>,
+        cancellation: Option<&CancellationToken>,
+    ) -> (Vec<String>, bool) {
+        let max_expansions = self.settings.read().unwrap().max_expansions;
+        let terms = Self::scan_vocabulary(index, cancellation)
+            .filter(|word| word.starts_with(prefix))
+            .cloned()
+            .collect();
+
+        Self::cap_expansions(terms, index, max_expansions)
+    }
+
+    /// Every indexed term, ending early once `cancellation` is set.
+    fn scan_vocabulary<'a>(
+        index: &'a HashMap<String, Vec<DocHandle>>,
+        cancellation: Option<&'a CancellationToken>,
+    ) -> impl Iterator<Item = &'a String> {
+        index
+            .keys()
+            .enumerate()
+            .take_while(move |(i, _)| i % CANCELLATION_CHECK_INTERVAL != 0 || !is_cancelled(cancellation))
+            .map(|(_, word)| word)
+    }
+
+    /// Keeps the `max` most frequent terms, reporting whether any were dropped.
+    fn cap_expansions(
+        mut terms: Vec<String>,
+        index: &HashMap<String, Vec<DocHandle>>,
+        max: usize,
+    ) -> (Vec<String>, bool) {
+        if terms.len() <= max {
+            return (terms, false);
+        }
+
+        terms.sort_by_cached_key(|term| (Reverse(index.get(term).map_or(0, Vec::len)), term.clone()));
+        terms.truncate(max);
+        (terms, true)
+    }
+
+    /// True if some position holds a term of the first group, the next
+    /// position a term of the second group, and so on.
+    fn contains_phrase(positions: &TermPositions, term_groups: &[Vec<String>]) -> bool {
+        let Some(first) = term_groups.first() else {
+            return false;
+        };
+
+        first
+            .iter()
+            .filter_map(|term| positions.get(term))
+            .flatten()
+            .any(|&start| {
+                term_groups.iter().enumerate().skip(1).all(|(offset, terms)| {
+                    terms.iter().any(|term| {
+                        positions
+                            .get(term)
+                            .is_some_and(|p| p.contains(&(start + offset)))
+                    })
+                })
+            })
+    }
+
+    /// Smallest gap between occurrences of two different query terms.
+    fn min_term_distance(positions: &TermPositions, term_groups: &[Vec<String>]) -> Option<usize> {
+        let mut occurrences: Vec<(usize, usize)> = term_groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, terms)| {
+                terms
+                    .iter()
+                    .filter_map(|term| positions.get(term))
+                    .flatten()
+                    .map(move |&position| (position, group))
+            })
+            .collect();
+        occurrences.sort_unstable();
+
+        occurrences
+            .windows(2)
+            .filter(|pair| pair[0].1 != pair[1].1)
+            .map(|pair| pair[1].0 - pair[0].0)
+            .min()
+    }
+
+    fn edit_distance(&self, a: &str, b: &str) -> usize {
+        let a_chars: Vec<char> = a.chars().collect();
+        let b_chars: Vec<char> = b.chars().collect();
+        let mut dp = vec![vec![0; b_chars.len() + 1]; a_chars.len() + 1];
+
+        for (i, row) in dp.iter_mut().enumerate() {
+            row[0] = i;
+        }
+        for (j, cell) in dp[0].iter_mut().enumerate() {
+            *cell = j;
+        }
+
+        for i in 1..=a_chars.len() {
+            for j in 1..=b_chars.len() {
+                let cost = if a_chars[i-1] == b_chars[j-1] { 0 } else { 1 };
+                dp[i][j] = std::cmp::min(
+                    std::cmp::min(dp[i-1][j] + 1, dp[i][j-1] + 1),
+                    dp[i-1][j-1] + cost
+                );
+            }
+        }
+
+        dp[a_chars.len()][b_chars.len()]
+    }
+
+    /// Edit distance from `needle` to its closest substring of `haystack`.
+    fn substring_edit_distance(&self, needle: &str, haystack: &str) -> usize {
+        let haystack: Vec<char> = haystack.chars().collect();
+        let mut prev = vec![0; haystack.len() + 1];
+        for (i, n) in needle.chars().enumerate() {
+            let mut row = vec![i + 1; haystack.len() + 1];
+            for (j, &h) in haystack.iter().enumerate() {
+                let cost = if n == h { 0 } else { 1 };
+                row[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1);
+            }
+            prev = row;
+        }
+        prev.into_iter().min().unwrap_or(0)
+    }
+
+    fn generate_highlights(&self, doc: &Document, tokens: &[String], mode: HighlightMode, max: usize) -> Vec<String> {
+        let full_text = format!("{} {}", doc.title, doc.content);
+        let mut highlights = Vec::new();
+
+        let anchors = match mode {
+            HighlightMode::FirstOccurrence | HighlightMode::SentenceWindow => tokens
+                .iter()
+                .filter_map(|token| {
+                    self.find_surface_match(&full_text, token).or_else(|| {
+                        full_text
+                            .to_lowercase()
+                            .find(&token.to_lowercase())
+                            .map(|start| (start, start + token.len()))
+                            .filter(|&(start, end)| {
+                                full_text.is_char_boundary(start) && full_text.is_char_boundary(end)
+                            })
+                    })
+                })
+                .collect(),
+            HighlightMode::Density => self.densest_anchors(&full_text, tokens),
+        };
+
+        for (start, end) in anchors {
+            if highlights.len() >= max {
+                break;
+            }
+
+            let highlight = if mode == HighlightMode::SentenceWindow {
+                let (context_start, context_end) = Self::sentence_bounds(&full_text, start, end, doc.title.len());
+                self.mark_terms(&full_text, context_start, context_end, tokens, (start, end))
+            } else {
+                self.window_snippet(&full_text, (start, end), tokens)
+            };
+
+            // Skip snippets that repeat (or are contained in) one already kept
+            let core = Self::snippet_core(&highlight);
+            let duplicate = highlights.iter().any(|existing: &String| {
+                let existing = Self::snippet_core(existing);
+                existing.contains(core) || core.contains(existing)
+            });
+            if !duplicate {
+                highlights.push(highlight);
+            }
+        }
+
+        highlights
+    }
+
+    /// Snippets per field containing a query term.
+    fn generate_field_highlights(
+        &self,
+        doc: &Document,
+        tokens: &[String],
+        config: &HighlightConfig,
+    ) -> HashMap<String, Vec<String>> {
+        let searchable = self.settings.read().unwrap().searchable_metadata.clone();
+        let metadata = searchable
+            .iter()
+            .filter_map(|key| Some((key.as_str(), doc.metadata.get(key)?.as_str())));
+        [("title", doc.title.as_str()), ("content", doc.content.as_str())]
+            .into_iter()
+            .filter(|(field, _)| config.field_max_snippets.contains_key(*field))
+            .chain(metadata)
+            .filter_map(|(field, text)| {
+                let snippets = self.field_snippets(text, tokens, config.limit_for(field));
+                (!snippets.is_empty()).then(|| (field.to_string(), snippets))
+            })
+            .collect()
+    }
+
+    /// Up to `limit` non-overlapping windows around query terms in `text`.
+    fn field_snippets(&self, text: &str, tokens: &[String], limit: usize) -> Vec<String> {
+        let mut snippets = Vec::new();
+        let mut covered_until = 0;
+        for (start, end) in Self::word_spans(text) {
+            if snippets.len() >= limit {
+                break;
+            }
+            if (!snippets.is_empty() && start < covered_until)
+                || !self.tokenize(&text[start..end]).iter().any(|t| tokens.contains(t))
+            {
+                continue;
+            }
+            snippets.push(self.window_snippet(text, (start, end), tokens));
+            covered_until = end + 50;
+        }
+        snippets
+    }
+
+    /// The ±50-byte window around `anchor` with query terms marked.
+    fn window_snippet(&self, text: &str, anchor: (usize, usize), tokens: &[String]) -> String {
+        let (start, end) = anchor;
+        let mut context_start = start.saturating_sub(50);
+        while !text.is_char_boundary(context_start) {
+            context_start -= 1;
+        }
+        let mut context_end = std::cmp::min(end + 50, text.len());
+        while !text.is_char_boundary(context_end) {
+            context_end += 1;
+        }
+
+        let mut snippet = self.mark_terms(text, context_start, context_end, tokens, anchor);
+        if context_start > 0 {
+            snippet = format!("...{}", snippet);
+        }
+        if context_end < text.len() {
+            snippet = format!("{}...", snippet);
+        }
+        snippet
+    }
+
+    /// The window of `doc` containing the most distinct query terms.
+    pub fn best_snippet(&self, doc: &Document, tokens: &[String]) -> Option<String> {
+        let full_text = format!("{} {}", doc.title, doc.content);
+        let anchor = *self.densest_anchors(&full_text, tokens).first()?;
+        Some(self.window_snippet(&full_text, anchor, tokens))
+    }
+
+    /// Byte range of the sentence containing `start..end`.
+    fn sentence_bounds(text: &str, start: usize, end: usize, title_len: usize) -> (usize, usize) {
+        let is_delimiter = |c: char| matches!(c, '.' | '!' | '?' | '\n');
+        let (lower, upper) = if start < title_len {
+            (0, title_len)
+        } else {
+            ((title_len + 1).min(text.len()), text.len())
+        };
+
+        let mut from = text[lower..start].rfind(is_delimiter).map_or(lower, |i| lower + i + 1);
+        from += text[from..start].len() - text[from..start].trim_start().len();
+        let to = text[end..upper].find(is_delimiter).map_or(upper, |i| end + i + 1);
+        (from, to)
+    }
+
+    /// Query-term occurrences in `text`, densest highlight window first.
+    fn densest_anchors(&self, text: &str, tokens: &[String]) -> Vec<(usize, usize)> {
+        let occurrences: Vec<((usize, usize), Vec<String>)> = Self::word_spans(text)
+            .into_iter()
+            .filter_map(|(start, end)| {
+                let matched: Vec<String> = self
+                    .tokenize(&text[start..end])
+                    .into_iter()
+                    .filter(|t| tokens.contains(t))
+                    .collect();
+                (!matched.is_empty()).then_some(((start, end), matched))
+            })
+            .collect();
+
+        let mut anchors: Vec<((usize, usize), usize)> = occurrences
+            .iter()
+            .map(|&((start, end), _)| {
+                let (from, to) = (start.saturating_sub(50), end + 50);
+                let distinct: HashSet<&String> = occurrences
+                    .iter()
+                    .filter(|((s, e), _)| *s < to && *e > from)
+                    .flat_map(|(_, matched)| matched)
+                    .collect();
+                ((start, end), distinct.len())
+            })
+            .collect();
+        anchors.sort_by_key(|&(_, distinct)| Reverse(distinct));
+        anchors.into_iter().map(|(span, _)| span).collect()
+    }
+
+    /// Copies `text[from..to]` with query words wrapped in `<mark>` tags.
+    fn mark_terms(
+        &self,
+        text: &str,
+        from: usize,
+        to: usize,
+        tokens: &[String],
+        anchor: (usize, usize),
+    ) -> String {
+        let mut spans: Vec<(usize, usize)> = Self::word_spans(text)
+            .into_iter()
+            .filter(|&(start, end)| start < to && end > from)
+            .filter(|&(start, end)| self.tokenize(&text[start..end]).iter().any(|t| tokens.contains(t)))
+            .filter_map(|(start, end)| Self::trim_to_alphanumeric(text, start, end))
+            .collect();
+        if let Some(anchor) = Self::trim_to_alphanumeric(text, anchor.0, anchor.1) {
+            if !spans.iter().any(|&(start, end)| start < anchor.1 && end > anchor.0) {
+                spans.push(anchor);
+            }
+        }
+        spans.sort_unstable();
+
+        let mut marked = String::new();
+        let mut cursor = from;
+        for (start, end) in spans {
+            let (start, end) = (start.max(cursor), end.min(to));
+            if start >= end {
+                continue;
+            }
+            marked.push_str(&escape_html(&text[cursor..start]));
+            marked.push_str("<mark>");
+            marked.push_str(&escape_html(&text[start..end]));
+            marked.push_str("</mark>");
+            cursor = end;
+        }
+        marked.push_str(&escape_html(&text[cursor..to]));
+        marked
+    }
+
+    /// Byte spans of the whitespace-separated words of `text`.
+    fn word_spans(text: &str) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        let mut word_start = None;
+        for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+            if c.is_whitespace() {
+                if let Some(start) = word_start.take() {
+                    spans.push((start, i));
+                }
+            } else if word_start.is_none() {
+                word_start = Some(i);
+            }
+        }
+        spans
+    }
+
+    /// Narrows a span to exclude leading/trailing punctuation ("Rust," -> "Rust").
+    fn trim_to_alphanumeric(text: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+        let word = &text[start..end];
+        let leading = word.len() - word.trim_start_matches(|c: char| !c.is_alphanumeric()).len();
+        let trimmed = word.trim_matches(|c: char| !c.is_alphanumeric());
+        (!trimmed.is_empty()).then(|| (start + leading, start + leading + trimmed.len()))
+    }
+
+    /// Byte span of the first word in `text` that analyzes to `token`.
+    fn find_surface_match(&self, text: &str, token: &str) -> Option<(usize, usize)> {
+        Self::word_spans(text)
+            .into_iter()
+            .find(|&(start, end)| self.tokenize(&text[start..end]).iter().any(|t| t == token))
+    }
+
+    fn snippet_core(snippet: &str) -> &str {
+        snippet.trim_start_matches("...").trim_end_matches("...").trim()
+    }
+
+    fn build_result(
+        &self,
+        doc: &Document,
+        score: f32,
+        highlights: Vec<String>,
+        matched_terms: Vec<String>,
+        content_len: Option<usize>,
+    ) -> SearchResult {
+        let content = match content_len {
+            Some(max_len) => self.truncate_content(&doc.content, max_len),
+            None => doc.content.clone(),
+        };
+
+        SearchResult {
+            id: doc.id.clone(),
+            title: doc.title.clone(),
+            content,
+            score,
+            highlights,
+            metadata: doc.metadata.clone(),
+            matched_terms,
+            field_highlights: HashMap::new(),
+            joined_highlight: None,
+            term_scores: HashMap::new(),
+            fallback: false,
+        }
+    }
+
+    fn truncate_content(&self, content: &str, max_len: usize) -> String {
+        match content.char_indices().nth(max_len) {
+            Some((end, _)) => format!("{}...", &content[..end]),
+            None => content.to_string(),
+        }
+    }
+
+    // ==================== STATS & MONITORING ====================
+
+    /// Whether the engine holds no documents.
+    pub fn is_empty(&self) -> bool {
+        *self.total_documents.read().unwrap() == 0
+    }
+
+    pub fn get_stats(&self) -> IndexStats {
+        let total_docs = *self.total_documents.read().unwrap();
+        let estimated_size = total_docs * 1024; // Rough estimation
+        
+        IndexStats {
+            total_documents: total_docs,
+            index_size_mb: estimated_size as f64 / 1024.0 / 1024.0,
+            last_updated: self.current_time(),
+            version: "1.0.0".to_string(),
+        }
+    }
+
+    /// Posting-list and deletion statistics for deciding when to `optimize`.
+    pub fn index_health(&self) -> IndexHealth {
+        let index = self.inverted_index.read().unwrap();
+        let postings: usize = index.values().map(Vec::len).sum();
+        let live = *self.total_documents.read().unwrap();
+        let deleted = *self.deleted_since_optimize.read().unwrap();
+
+        IndexHealth {
+            empty_postings: self.doc_handles.read().unwrap().free.len(),
+            avg_posting_len: if index.is_empty() { 0.0 } else { postings as f64 / index.len() as f64 },
+            vocabulary_size: index.len(),
+            deleted_ratio: if live + deleted == 0 { 0.0 } else { deleted as f64 / (live + deleted) as f64 },
+        }
+    }
+
+    /// Compacts posting lists and document handles, resetting `index_health`.
+    pub fn optimize(&self) {
+        let _swap_guard = self.swap_lock.read().unwrap();
+        let mut index = self.inverted_index.write().unwrap();
+        let remap = self.doc_handles.write().unwrap().compact();
+        let mut title_index = self.title_index.write().unwrap();
+        for postings in [&mut *index, &mut *title_index] {
+            postings.retain(|_, docs| !docs.is_empty());
+            for docs in postings.values_mut() {
+                for handle in docs.iter_mut() {
+                    *handle = remap[*handle as usize];
+                }
+                docs.shrink_to_fit();
+            }
+            postings.shrink_to_fit();
+        }
+        *self.deleted_since_optimize.write().unwrap() = 0;
+    }
+
+    /// When the engine last indexed `doc_id`, in seconds since the epoch.
+    pub fn indexed_at(&self, doc_id: &str) -> Option<u64> {
+        self.indexed_at.read().unwrap().get(doc_id).copied()
+    }
+
+    /// Ids of documents last indexed before `cutoff` (seconds since the
+    /// epoch), e.g. those not reindexed since an analyzer change.
+    pub fn documents_indexed_before(&self, cutoff: u64) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .indexed_at
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|&(_, &at)| at < cutoff)
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Clones every stored document, so callers iterate without blocking writers.
+    pub fn snapshot_documents(&self) -> Vec<Document> {
+        self.documents.read().unwrap().values().cloned().collect()
+    }
+
+    /// Counts stored documents per distinct value of `metadata_key`.
+    /// Documents without the key are not counted.
+    pub fn distribution(&self, metadata_key: &str) -> HashMap<String, usize> {
+        let docs = self.documents.read().unwrap();
+        let mut counts = HashMap::new();
+        for value in docs.values().filter_map(|doc| doc.metadata.get(metadata_key)) {
+            *counts.entry(value.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Documents whose title is exactly `title`.
+    pub fn find_by_title(&self, title: &str) -> Vec<Document> {
+        self.find_by_field("title", title, false)
+    }
+
+    /// Documents whose `field` (`id`, `title`, `content`, or metadata) equals `value`.
+    pub fn find_by_field(&self, field: &str, value: &str, ignore_case: bool) -> Vec<Document> {
+        let docs = self.documents.read().unwrap();
+        docs.values()
+            .filter(|doc| {
+                let actual = match field {
+                    "id" => Some(doc.id.as_str()),
+                    "title" => Some(doc.title.as_str()),
+                    "content" => Some(doc.content.as_str()),
+                    key => doc.metadata.get(key).map(String::as_str),
+                };
+                actual.is_some_and(|actual| {
+                    if ignore_case {
+                        actual.to_lowercase() == value.to_lowercase()
+                    } else {
+                        actual == value
+                    }
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Moves metadata key `from` to `to`, skipping documents that have `to`.
+    pub fn rename_metadata_key(&self, from: &str, to: &str) -> Result<usize, FerrumError> {
+        self.move_metadata_key(from, to, false)
+    }
+
+    /// Like `rename_metadata_key`, but replaces existing `to` values.
+    pub fn rename_metadata_key_overwriting(&self, from: &str, to: &str) -> Result<usize, FerrumError> {
+        self.move_metadata_key(from, to, true)
+    }
+
+    fn move_metadata_key(&self, from: &str, to: &str, overwrite: bool) -> Result<usize, FerrumError> {
+        if from.is_empty() || to.is_empty() {
+            return Err(FerrumError::InvalidArgument("Metadata keys must not be empty".to_string()));
+        }
+        if from == to {
+            return Err(FerrumError::InvalidArgument(format!(
+                "Cannot rename metadata key '{}' to itself",
+                from
+            )));
+        }
+
+        let swap_guard = self.swap_lock.read()?;
+        let mut docs = self.documents.write().unwrap();
+        let mut changes = Vec::new();
+        for doc in docs.values_mut() {
+            if !doc.metadata.contains_key(from) || (!overwrite && doc.metadata.contains_key(to)) {
+                continue;
+            }
+            if let Some(value) = doc.metadata.remove(from) {
+                doc.metadata.insert(to.to_string(), value);
+                changes.push(self.record_change(ChangeEvent::Added(doc.id.clone()), || ReplicationEvent::Upserted(doc.clone())));
+            }
+        }
+        drop(docs);
+        drop(swap_guard);
+        let changed = changes.len();
+        if changed > 0 {
+            self.bump_generation();
+        }
+        for change in changes {
+            self.notify(change);
+        }
+
+        Ok(changed)
+    }
+
+    pub fn bulk_import(&self, documents: Vec<Document>) -> Result<usize, FerrumError> {
+        let mut success_count = 0;
+        
+        for doc in documents {
+            match self.add_document(doc) {
+                Ok(_) => success_count += 1,
+                Err(e) => eprintln!("Failed to import document: {}", e),
+            }
+        }
+        
+        Ok(success_count)
+    }
+
+    /// Streams an NDJSON file of documents into the index, reporting failed lines.
+    pub fn import_file(&self, path: &Path) -> Result<BulkImportReport, FerrumError> {
+        let file = File::open(path)
+            .map_err(|e| FerrumError::Io(format!("Failed to open {}: {}", path.display(), e)))?;
+        let mut report = BulkImportReport::default();
+
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.map_err(|e| FerrumError::Io(format!("Failed to read line {}: {}", line_number, e)))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let result = serde_json::from_str::<Document>(&line)
+                .map_err(|e| FerrumError::Serialization(e.to_string()))
+                .and_then(|doc| self.add_document(doc));
+            match result {
+                Ok(()) => report.imported += 1,
+                Err(e) => report.errors.push((line_number, e)),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Writes the documents and their analyzed index to `path`, so
+    /// `load_from_file` can restore them without re-tokenizing.
+    pub fn save_to_file(&self, path: &Path, format: IndexFormat) -> Result<(), FerrumError> {
+        let snapshot = {
+            let _swap_guard = self.swap_lock.read()?;
+            IndexSnapshot {
+                documents: self.snapshot_documents(),
+                postings: {
+                    // Same order as `index_document`: the index, then the handles
+                    let index = self.inverted_index.read().unwrap();
+                    let doc_handles = self.doc_handles.read().unwrap();
+                    index
+                        .iter()
+                        .map(|(term, docs)| {
+                            (term.clone(), docs.iter().map(|&h| doc_handles.id(h).to_string()).collect())
+                        })
+                        .collect()
+                },
+                term_positions: self.term_positions.read().unwrap().clone(),
+                title_token_counts: self.title_token_counts.read().unwrap().clone(),
+                document_lengths: self.document_lengths.read().unwrap().clone(),
+                indexed_at: self.indexed_at.read().unwrap().clone(),
+                surface_terms: self.surface_terms.read().unwrap().clone(),
+            }
+        };
+        let bytes = match format {
+            IndexFormat::Json => serde_json::to_vec(&snapshot).map_err(|e| FerrumError::Serialization(e.to_string()))?,
+            IndexFormat::Binary => snapshot.to_binary(),
+        };
+        std::fs::write(path, bytes).map_err(|e| FerrumError::Io(format!("Failed to write {}: {}", path.display(), e)))
+    }
+
+    /// Replaces the indexed data with a `save_to_file` file; settings aren't stored.
+    pub fn load_from_file(&self, path: &Path, format: IndexFormat) -> Result<(), FerrumError> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| FerrumError::Io(format!("Failed to read {}: {}", path.display(), e)))?;
+        let snapshot = match format {
+            IndexFormat::Json => {
+                serde_json::from_slice(&bytes).map_err(|e| FerrumError::Serialization(e.to_string()))?
+            }
+            IndexFormat::Binary => IndexSnapshot::from_binary(&bytes)?,
+        };
+
+        let swap_guard = self.swap_lock.write()?;
+        let mut frequencies: HashMap<String, HashMap<String, f32>> = HashMap::new();
+        for (doc_id, positions) in &snapshot.term_positions {
+            let length = snapshot.document_lengths.get(doc_id).copied().unwrap_or(1).max(1);
+            frequencies.insert(
+                doc_id.clone(),
+                positions
+                    .iter()
+                    .map(|(term, at)| (term.clone(), at.len() as f32 / length as f32))
+                    .collect(),
+            );
+        }
+
+        let mut trie = TermTrie::default();
+        let phonetic = self.settings.read().unwrap().phonetic;
+        let mut phonetic_index: HashMap<String, HashSet<String>> = HashMap::new();
+        for (term, postings) in &snapshot.postings {
+            if postings.is_empty() {
+                continue;
+            }
+            trie.insert(term);
+            if let Some(code) = phonetic.and_then(|algorithm| algorithm.encode(term)) {
+                phonetic_index.entry(code).or_default().insert(term.clone());
+            }
+        }
+
+        let mut numeric_index: HashMap<String, NumericPostings> = HashMap::new();
+        for doc in &snapshot.documents {
+            for (key, &value) in &doc.numeric_metadata {
+                numeric_index.entry(key.clone()).or_default().push((value, doc.id.clone()));
+            }
+        }
+        for postings in numeric_index.values_mut() {
+            postings.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+
+        *self.total_documents.write().unwrap() = snapshot.documents.len();
+        let change = {
+            let mut docs = self.documents.write().unwrap();
+            *docs = snapshot.documents.into_iter().map(|doc| (doc.id.clone(), doc)).collect();
+            self.record_change(ChangeEvent::Replaced, || ReplicationEvent::Replaced(docs.values().cloned().collect()))
+        };
+        let mut doc_handles = DocInterner::default();
+        let index = snapshot
+            .postings
+            .into_iter()
+            .map(|(term, docs)| (term, docs.iter().map(|id| doc_handles.intern(id)).collect()))
+            .collect();
+        let mut title_index = HashMap::new();
+        for (doc_id, positions) in &snapshot.term_positions {
+            let title_len = snapshot.title_token_counts.get(doc_id).copied().unwrap_or(0);
+            let terms = positions.iter().filter(|(_, at)| at.iter().any(|&p| p < title_len)).map(|(term, _)| term);
+            if let Some(handle) = doc_handles.handle(doc_id) {
+                Self::update_title_postings(&mut title_index, handle, Vec::new(), terms.collect());
+            }
+        }
+        *self.inverted_index.write().unwrap() = index;
+        *self.title_index.write().unwrap() = title_index;
+        *self.doc_handles.write().unwrap() = doc_handles;
+        *self.word_frequencies.write().unwrap() = frequencies;
+        *self.document_lengths.write().unwrap() = snapshot.document_lengths;
+        *self.term_positions.write().unwrap() = snapshot.term_positions;
+        *self.title_token_counts.write().unwrap() = snapshot.title_token_counts;
+        *self.indexed_at.write().unwrap() = snapshot.indexed_at;
+        *self.surface_terms.write().unwrap() = snapshot.surface_terms;
+        *self.term_trie.write().unwrap() = trie;
+        *self.phonetic_index.write().unwrap() = phonetic_index;
+        *self.numeric_index.write().unwrap() = numeric_index;
+        *self.deleted_since_optimize.write().unwrap() = 0;
+        self.recompute_avg_doc_len();
+        self.bump_generation();
+        drop(swap_guard);
+        self.notify(change);
+        Ok(())
+    }
+
+    pub fn clear_index(&self) -> Result<(), FerrumError> {
+        let change = {
+            let _swap_guard = self.swap_lock.read()?;
+            let change = {
+                let mut docs = self.documents.write().unwrap();
+                *docs = HashMap::new();
+                self.record_change(ChangeEvent::Cleared, || ReplicationEvent::Cleared)
+            };
+            *self.inverted_index.write().unwrap() = HashMap::new();
+            *self.title_index.write().unwrap() = HashMap::new();
+            *self.doc_handles.write().unwrap() = DocInterner::default();
+            *self.word_frequencies.write().unwrap() = HashMap::new();
+            *self.document_lengths.write().unwrap() = HashMap::new();
+            *self.avg_doc_len.write().unwrap() = 0.0;
+            *self.term_positions.write().unwrap() = HashMap::new();
+            *self.title_token_counts.write().unwrap() = HashMap::new();
+            *self.indexed_at.write().unwrap() = HashMap::new();
+            *self.surface_terms.write().unwrap() = HashMap::new();
+            *self.term_trie.write().unwrap() = TermTrie::default();
+            *self.phonetic_index.write().unwrap() = HashMap::new();
+            *self.numeric_index.write().unwrap() = HashMap::new();
+            *self.total_documents.write().unwrap() = 0;
+            *self.deleted_since_optimize.write().unwrap() = 0;
+            self.bump_generation();
+            change
+        };
+        self.notify(change);
+        Ok(())
+    }
+
+    /// Re-indexes every document of `other` into `self`; returns the number merged.
+    pub fn merge_from(&self, other: &FerrumSearch) -> Result<usize, FerrumError> {
+        if std::ptr::eq(self, other) {
+            return Err(FerrumError::InvalidArgument("Cannot merge an engine into itself".to_string()));
+        }
+
+        let mut merged = 0;
+        for doc in other.snapshot_documents() {
+            self.add_document(doc)?;
+            merged += 1;
+        }
+        Ok(merged)
+    }
+
+    /// Atomically swaps in the index, analyzer, and settings of `other`.
+    pub fn replace_with(&self, other: FerrumSearch) {
+        fn take<T: Default>(lock: &RwLock<T>) -> T {
+            std::mem::take(&mut *lock.write().unwrap())
+        }
+
+        let swap_guard = self.swap_lock.write().unwrap();
+        let change = {
+            let mut docs = self.documents.write().unwrap();
+            *docs = take(&other.documents);
+            self.record_change(ChangeEvent::Replaced, || ReplicationEvent::Replaced(docs.values().cloned().collect()))
+        };
+        *self.inverted_index.write().unwrap() = take(&other.inverted_index);
+        *self.title_index.write().unwrap() = take(&other.title_index);
+        *self.doc_handles.write().unwrap() = take(&other.doc_handles);
+        *self.word_frequencies.write().unwrap() = take(&other.word_frequencies);
+        *self.document_lengths.write().unwrap() = take(&other.document_lengths);
+        *self.avg_doc_len.write().unwrap() = take(&other.avg_doc_len);
+        *self.term_positions.write().unwrap() = take(&other.term_positions);
+        *self.title_token_counts.write().unwrap() = take(&other.title_token_counts);
+        *self.indexed_at.write().unwrap() = take(&other.indexed_at);
+        *self.surface_terms.write().unwrap() = take(&other.surface_terms);
+        *self.term_trie.write().unwrap() = take(&other.term_trie);
+        *self.phonetic_index.write().unwrap() = take(&other.phonetic_index);
+        *self.numeric_index.write().unwrap() = take(&other.numeric_index);
+        *self.total_documents.write().unwrap() = take(&other.total_documents);
+        *self.settings.write().unwrap() = take(&other.settings);
+        *self.analyzer.write().unwrap() = take(&other.analyzer);
+        *self.field_analyzers.write().unwrap() = take(&other.field_analyzers);
+        *self.stop_words.write().unwrap() = take(&other.stop_words);
+        *self.deleted_since_optimize.write().unwrap() = take(&other.deleted_since_optimize);
+        self.bump_generation();
+        drop(swap_guard);
+        self.notify(change);
+    }
+}
+
+fn demo_documents() -> Vec<Document> {
+    vec![
+        Document {
+            id: "rust-guide".to_string(),
+            title: "The Rust Programming Language Guide".to_string(),
+            content: "Rust is a systems programming language that runs blazingly fast, prevents segfaults, and guarantees thread safety. It accomplishes these goals by being memory safe without using garbage collection.".to_string(),
+            metadata: {
+                let mut meta = HashMap::new();
+                meta.insert("category".to_string(), "programming".to_string());
+                meta.insert("difficulty".to_string(), "intermediate".to_string());
+                meta
+            },
+            timestamp: 1640995200,
+            numeric_metadata: HashMap::new(),
+            acl: Vec::new(),
+        },
+        Document {
+            id: "web-dev-trends".to_string(),
+            title: "Modern Web Development Trends 2024".to_string(),
+            content: "Web development continues to evolve with new frameworks, tools, and best practices. React, Vue, and Angular dominate the frontend landscape while Node.js powers many backend applications.".to_string(),
+            metadata: {
+                let mut meta = HashMap::new();
+                meta.insert("category".to_string(), "web".to_string());
+                meta.insert("year".to_string(), "2024".to_string());
+                meta
+            },
+            timestamp: 1704067200,
+            numeric_metadata: HashMap::new(),
+            acl: Vec::new(),
+        },
+        Document {
+            id: "search-algorithms".to_string(),
+            title: "Understanding Search Algorithms".to_string(),
+            content: "Search algorithms are fundamental to computer science. From simple linear search to complex full-text search engines, understanding how search works is crucial for building efficient applications.".to_string(),
+            metadata: {
+                let mut meta = HashMap::new();
+                meta.insert("category".to_string(), "algorithms".to_string());
+                meta.insert("difficulty".to_string(), "advanced".to_string());
+                meta
+            },
+            timestamp: 1672531200,
+            numeric_metadata: HashMap::new(),
+            acl: Vec::new(),
+        },
+    ]
+}
+
+fn main() {
+    println!("🔍 FerrumSearch - High-Performance Search Engine");
+    println!("================================================");
+    
+    let engine = FerrumSearch::new();
+    
+
+    // Import demo data
+    match engine.bulk_import(demo_documents()) {
+        Ok(count) => println!("✅ Successfully imported {} documents", count),
+        Err(e) => println!("❌ Import failed: {}", e),
+    }
+
+    // Demo searches
+    println!("\n🔍 Demo Searches:");
+    println!("=================");
+
+    // Basic search
+    let query = SearchQuery {
+        query: "rust programming".to_string(),
+        ..Default::default()
+    };
+    
+    match engine.search(query) {
+        Ok(response) => {
+            println!("\n📊 Query: 'rust programming' ({}ms)", response.query_time_ms);
+            println!("   Results: {}/{}", response.results.len(), response.total_hits);
+            for result in &response.results {
+                println!("   • {} (score: {:.2})", result.title, result.score);
+            }
+        },
+        Err(e) => println!("❌ Search failed: {}", e),
+    }
+
+    // Fuzzy search
+    let fuzzy_query = SearchQuery {
+        query: "algoritms".to_string(), // Typo intentional
+        fuzzy: true,
+        ..Default::default()
+    };
+    
+    match engine.search(fuzzy_query) {
+        Ok(response) => {
+            println!("\n📊 Fuzzy Query: 'algoritms' ({}ms)", response.query_time_ms);
+            println!("   Results: {}/{}", response.results.len(), response.total_hits);
+            for result in &response.results {
+                println!("   • {} (score: {:.2})", result.title, result.score);
+            }
+        },
+        Err(e) => println!("❌ Fuzzy search failed: {}", e),
+    }
+
+    // Filtered search
+    let filtered_query = SearchQuery {
+        query: "development".to_string(),
+        filters: Some({
+            let mut filters = HashMap::new();
+            filters.insert("category".to_string(), "web".to_string());
+            filters
+        }),
+        ..Default::default()
     };
     
-    match engine.search(fuzzy_query) {
+    match engine.search(filtered_query) {
         Ok(response) => {
-            println!("\n📊 Fuzzy Query: 'algoritms' ({}ms)", response.query_time_ms);
+            println!("\n📊 Filtered Query: 'development' + category:web ({}ms)", response.query_time_ms);
             println!("   Results: {}/{}", response.results.len(), response.total_hits);
             for result in &response.results {
                 println!("   • {} (score: {:.2})", result.title, result.score);
             }
-        },
-        Err(e) => println!("❌ Fuzzy search failed: {}", e),
+        },
+        Err(e) => println!("❌ Filtered search failed: {}", e),
+    }
+
+    // Autocomplete demo
+    println!("\n🔤 Autocomplete for 'prog':");
+    let suggestions = engine.autocomplete("prog", 5);
+    for suggestion in suggestions {
+        println!("   • {}", suggestion);
+    }
+
+    // Stats
+    let stats = engine.get_stats();
+    println!("\n📈 Index Statistics:");
+    println!("   Documents: {}", stats.total_documents);
+    println!("   Index Size: {:.2} MB", stats.index_size_mb);
+    println!("   Version: {}", stats.version);
+    
+    println!("\n🚀 FerrumSearch is ready for production!");
+}
+
+// ==================== DEMO & TESTING ====================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_doc(id: &str, title: &str, content: &str) -> Document {
+        Document {
+            id: id.to_string(),
+            title: title.to_string(),
+            content: content.to_string(),
+            metadata: HashMap::new(),
+            timestamp: 0,
+            numeric_metadata: HashMap::new(),
+            acl: Vec::new(),
+        }
+    }
+
+    /// Ids in `term`'s posting list, in posting order.
+    /// Adds `n` unrelated documents, keeping test terms' IDF positive.
+    fn add_fillers(engine: &FerrumSearch, n: usize) {
+        for i in 0..n {
+            engine.add_document(make_doc(&format!("filler{}", i), "Other", "unrelated")).unwrap();
+        }
+    }
+
+    fn posting_ids(engine: &FerrumSearch, term: &str) -> Option<Vec<String>> {
+        let doc_handles = engine.doc_handles.read().unwrap();
+        let index = engine.inverted_index.read().unwrap();
+        let postings = index.get(term)?;
+        Some(postings.iter().map(|&h| doc_handles.id(h).to_string()).collect())
+    }
+
+    #[test]
+    fn test_basic_search() {
+        let engine = FerrumSearch::new();
+        
+        let doc1 = Document {
+            id: "1".to_string(),
+            title: "Rust Programming".to_string(),
+            content: "Rust is a systems programming language focused on safety and performance".to_string(),
+            metadata: HashMap::new(),
+            timestamp: 0,
+            numeric_metadata: HashMap::new(),
+            acl: Vec::new(),
+        };
+
+        let doc2 = Document {
+            id: "2".to_string(),
+            title: "Web Development".to_string(),
+            content: "Building web applications with modern frameworks and tools".to_string(),
+            metadata: HashMap::new(),
+            timestamp: 0,
+            numeric_metadata: HashMap::new(),
+            acl: Vec::new(),
+        };
+
+        engine.add_document(doc1).unwrap();
+        engine.add_document(doc2).unwrap();
+
+        let query = SearchQuery {
+            query: "rust programming".to_string(),
+            ..Default::default()
+        };
+
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "1");
+    }
+
+    #[test]
+    fn test_fuzzy_search() {
+        let engine = FerrumSearch::new();
+        
+        let doc = Document {
+            id: "1".to_string(),
+            title: "Programming".to_string(),
+            content: "Advanced programming concepts".to_string(),
+            metadata: HashMap::new(),
+            timestamp: 0,
+            numeric_metadata: HashMap::new(),
+            acl: Vec::new(),
+        };
+
+        engine.add_document(doc).unwrap();
+
+        let query = SearchQuery {
+            query: "programing".to_string(), // Typo
+            fuzzy: true,
+            ..Default::default()
+        };
+
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+    }
+
+    #[test]
+    fn test_insert_document_rejects_duplicate_id() {
+        let engine = FerrumSearch::new();
+
+        engine.insert_document(make_doc("1", "Rust Programming", "Original content")).unwrap();
+
+        let result = engine.insert_document(make_doc("1", "Replacement", "Clobbered content"));
+        assert_eq!(result, Err(FerrumError::DuplicateId("1".to_string())));
+
+        let query = SearchQuery {
+            query: "original".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].title, "Rust Programming");
+        assert_eq!(engine.get_stats().total_documents, 1);
+
+        // add_document keeps upsert semantics
+        engine.add_document(make_doc("1", "Replacement", "Clobbered content")).unwrap();
+        assert_eq!(engine.get_stats().total_documents, 1);
+    }
+
+    #[test]
+    fn test_highlights_are_deduplicated() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "Rust loves rust and rust loves safety")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust loves safety".to_string(),
+            ..Default::default()
+        };
+
+        let results = engine.search(query).unwrap();
+        let highlights = &results.results[0].highlights;
+        assert_eq!(highlights.len(), 1);
+        assert_eq!(
+            highlights[0],
+            "<mark>Rust</mark> <mark>Rust</mark> <mark>loves</mark> <mark>rust</mark> and \
+             <mark>rust</mark> <mark>loves</mark> <mark>safety</mark>"
+        );
+    }
+
+    #[test]
+    fn test_phrase_prefix_search() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("web", "Modern Web Development", "Frameworks and tools")).unwrap();
+        engine.add_document(make_doc("rust", "Rust Programming", "Systems programming language")).unwrap();
+        engine.add_document(make_doc("scattered", "Development Notes", "Notes about the web")).unwrap();
+
+        let query = SearchQuery {
+            query: "web deve".to_string(),
+            query_type: QueryType::PhrasePrefix,
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "web");
+
+        let query = SearchQuery {
+            query: "web xyz".to_string(),
+            query_type: QueryType::PhrasePrefix,
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 0);
+    }
+
+    #[test]
+    fn test_max_expansions_caps_fuzzy_terms() {
+        let engine = FerrumSearch::new();
+        for (i, word) in ["bat", "hat", "mat", "rat", "cap", "cot", "cut"].iter().enumerate() {
+            engine.add_document(make_doc(&i.to_string(), word, "")).unwrap();
+        }
+        // Make "hat" the most frequent neighbour so it survives the cap
+        engine.add_document(make_doc("extra", "hat hat", "")).unwrap();
+
+        let query = SearchQuery {
+            query: "cat".to_string(),
+            fuzzy: true,
+            ..Default::default()
+        };
+        let results = engine.search(query.clone()).unwrap();
+        assert_eq!(results.total_hits, 8);
+        assert!(!results.truncated_expansion);
+
+        engine.set_max_expansions(1);
+        let results = engine.search(query).unwrap();
+        assert!(results.truncated_expansion);
+        assert_eq!(results.total_hits, 2);
+        assert!(results.results.iter().all(|r| r.title.starts_with("hat")));
+    }
+
+    #[test]
+    fn test_snapshot_documents_is_independent() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust Programming", "Systems language")).unwrap();
+        engine.add_document(make_doc("2", "Web Development", "Frameworks")).unwrap();
+
+        let snapshot = engine.snapshot_documents();
+
+        engine.remove_document("1").unwrap();
+        engine.add_document(make_doc("2", "Changed", "Rewritten")).unwrap();
+        engine.add_document(make_doc("3", "Search Algorithms", "Indexes")).unwrap();
+
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot.iter().any(|d| d.id == "1"));
+        let doc2 = snapshot.iter().find(|d| d.id == "2").unwrap();
+        assert_eq!(doc2.title, "Web Development");
+    }
+
+    #[test]
+    fn test_stemmed_highlights_use_surface_form() {
+        let engine = FerrumSearch::new();
+        engine.set_stemming(true);
+        engine.add_document(make_doc(
+            "1",
+            "Morning runners",
+            "The park fills up early with people walking dogs and chatting, but she keeps running past them all",
+        )).unwrap();
+
+        let query = SearchQuery {
+            query: "running".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+
+        let highlight = &results.results[0].highlights[0];
+        assert!(highlight.contains("running"));
+        assert!(!highlight.contains("runners"));
+    }
+
+    #[test]
+    fn test_custom_analyzer_pipeline() {
+        let mut synonyms = HashMap::new();
+        synonyms.insert("cars".to_string(), vec!["automobiles".to_string()]);
+
+        let analyzer = Analyzer::new()
+            .with_filter(Lowercase)
+            .with_filter(DiacriticFold)
+            .with_filter(StopWords::english())
+            .with_filter(Synonym::new(synonyms))
+            .with_filter(Stem);
+
+        assert_eq!(
+            analyzer.analyze("The Café sells CARS"),
+            vec!["cafe", "sell", "car", "automobile"]
+        );
+
+        let engine = FerrumSearch::new();
+        engine.set_analyzer(analyzer);
+        engine.add_document(make_doc("1", "Café", "Used cars for sale")).unwrap();
+
+        let query = SearchQuery {
+            query: "automobile".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(query).unwrap().total_hits, 1);
+    }
+
+    #[test]
+    fn test_append_content_indexes_new_terms() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("log", "Server log", "startup complete")).unwrap();
+
+        engine.append_content("log", "connection refused").unwrap();
+        assert_eq!(
+            engine.append_content("missing", "anything"),
+            Err(FerrumError::NotFound("missing".to_string()))
+        );
+
+        for term in ["startup", "refused"] {
+            let query = SearchQuery {
+                query: term.to_string(),
+                ..Default::default()
+            };
+            assert_eq!(engine.search(query).unwrap().total_hits, 1, "term {}", term);
+        }
+
+        let phrase = SearchQuery {
+            query: "connection refused".to_string(),
+            query_type: QueryType::Phrase,
+            ..Default::default()
+        };
+        assert_eq!(engine.search(phrase).unwrap().total_hits, 1);
+        assert_eq!(*engine.document_lengths.read().unwrap().get("log").unwrap(), 6);
+    }
+
+    #[test]
+    fn test_proximity_boost_prefers_adjacent_terms() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("adjacent", "Notes", "rust safety one two three four")).unwrap();
+        engine.add_document(make_doc("scattered", "Notes", "rust one two three four safety")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust safety".to_string(),
+            ..Default::default()
+        };
+
+        let results = engine.search(query.clone()).unwrap();
+        assert_eq!(results.results[0].score, results.results[1].score);
+
+        engine.set_proximity_boost(1.0);
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.results[0].id, "adjacent");
+        assert!(results.results[0].score > results.results[1].score);
+    }
+
+    #[test]
+    fn test_import_file_reports_malformed_lines() {
+        let path = std::env::temp_dir().join(format!("ferrumsearch-import-{}.ndjson", Uuid::new_v4()));
+        let lines = [
+            serde_json::to_string(&make_doc("1", "Rust Programming", "Systems language")).unwrap(),
+            "{ not valid json".to_string(),
+            String::new(),
+            serde_json::to_string(&make_doc("2", "Web Development", "Frameworks")).unwrap(),
+        ];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let engine = FerrumSearch::new();
+        let report = engine.import_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, 2);
+        assert!(matches!(report.errors[0].1, FerrumError::Serialization(_)));
+        assert_eq!(engine.get_stats().total_documents, 2);
+    }
+
+    #[test]
+    fn test_reranker_reorders_before_pagination() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "rust rust rust")).unwrap();
+        engine.add_document(make_doc("2", "Rust", "rust notes here")).unwrap();
+        engine.add_document(make_doc("3", "Rust", "rust notes here and there again")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        let baseline: Vec<String> = engine
+            .search(query.clone())
+            .unwrap()
+            .results
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+
+        engine.set_reranker(10, Box::new(|results: &mut Vec<SearchResult>| results.reverse()));
+
+        let first_page = SearchQuery {
+            per_page: Some(1),
+            ..query
+        };
+        let results = engine.search(first_page).unwrap();
+        assert_eq!(results.total_hits, 3);
+        assert_eq!(results.results[0].id, baseline[2]);
+    }
+
+    #[test]
+    fn test_negated_terms_exclude_documents() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("web", "Web Development", "Building web applications")).unwrap();
+        engine.add_document(make_doc("process", "Software Development", "Agile process and planning")).unwrap();
+        engine.add_document(make_doc("rust", "Rust Programming", "Systems language")).unwrap();
+
+        let query = SearchQuery {
+            query: "development -web".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "process");
+    }
+
+    #[test]
+    fn test_result_content_len() {
+        let engine = FerrumSearch::new();
+        let content = "rust ".repeat(60);
+        engine.add_document(make_doc("1", "Rust", &content)).unwrap();
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            result_content_len: Some(50),
+            ..Default::default()
+        };
+        let results = engine.search(query.clone()).unwrap();
+        assert_eq!(results.results[0].content, format!("{}...", &content[..50]));
+
+        let full = SearchQuery {
+            result_content_len: None,
+            ..query
+        };
+        assert_eq!(engine.search(full).unwrap().results[0].content, content);
+    }
+
+    #[test]
+    fn test_metadata_distribution() {
+        let engine = FerrumSearch::new();
+        engine.bulk_import(demo_documents()).unwrap();
+        engine.add_document(Document {
+            metadata: HashMap::from([("category".to_string(), "web".to_string())]),
+            ..make_doc("web-2", "Frontend Frameworks", "React and Vue")
+        }).unwrap();
+
+        let categories = engine.distribution("category");
+        assert_eq!(categories.len(), 3);
+        assert_eq!(categories["web"], 2);
+        assert_eq!(categories["programming"], 1);
+        assert_eq!(categories["algorithms"], 1);
+
+        let years = engine.distribution("year");
+        assert_eq!(years["2024"], 1);
+        assert!(engine.distribution("missing").is_empty());
+    }
+
+    #[test]
+    fn test_max_indexed_tokens_caps_document_length() {
+        let engine = FerrumSearch::new();
+        engine.set_max_indexed_tokens(Some(100));
+
+        let content = "word ".repeat(5000) + "tail";
+        engine.add_document(make_doc("big", "Huge", &content)).unwrap();
+
+        assert_eq!(*engine.document_lengths.read().unwrap().get("big").unwrap(), 100);
+        assert_eq!(engine.snapshot_documents()[0].content, content);
+
+        // Tokens past the cap are not searchable
+        let query = SearchQuery {
+            query: "tail".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(query).unwrap().total_hits, 0);
+
+        engine.append_content("big", "appended").unwrap();
+        assert_eq!(*engine.document_lengths.read().unwrap().get("big").unwrap(), 100);
+    }
+
+    #[test]
+    fn test_change_listener_records_mutations() {
+        use std::sync::Mutex;
+
+        let engine = FerrumSearch::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        engine.set_change_listener(Box::new(move |event| recorded.lock().unwrap().push(event)));
+
+        engine.add_document(make_doc("1", "Rust", "Systems")).unwrap();
+        engine.add_document(make_doc("2", "Web", "Frameworks")).unwrap();
+        engine.remove_document("1").unwrap();
+        engine.remove_document("missing").unwrap();
+        engine.clear_index().unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                ChangeEvent::Added("1".to_string()),
+                ChangeEvent::Added("2".to_string()),
+                ChangeEvent::Removed("1".to_string()),
+                ChangeEvent::Cleared,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_change_listener_can_search_the_updated_index() {
+        use std::sync::Mutex;
+
+        let engine = Arc::new(FerrumSearch::new());
+        add_fillers(&engine, 4);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (searcher, recorded) = (Arc::downgrade(&engine), Arc::clone(&seen));
+        engine.set_change_listener(Box::new(move |event| {
+            let Some(engine) = searcher.upgrade() else { return };
+            let query = SearchQuery { query: "rust".to_string(), ..Default::default() };
+            recorded.lock().unwrap().push((event, engine.search(query).unwrap().total_hits));
+        }));
+
+        engine.add_document(make_doc("1", "Rust", "Systems")).unwrap();
+        engine.remove_document("1").unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(ChangeEvent::Added("1".to_string()), 1), (ChangeEvent::Removed("1".to_string()), 0)]
+        );
+    }
+
+    #[test]
+    fn test_exact_match_boost_prefers_literal_word() {
+        let engine = FerrumSearch::new();
+        engine.set_stemming(true);
+        engine.add_document(make_doc("exact", "Notes", "cats play outside")).unwrap();
+        engine.add_document(make_doc("inflected", "Notes", "cat plays outside")).unwrap();
+        for i in 0..3 {
+            engine.add_document(make_doc(&format!("filler-{}", i), "Notes", "dogs bark loudly")).unwrap();
+        }
+
+        let query = SearchQuery {
+            query: "cats".to_string(),
+            ..Default::default()
+        };
+
+        let results = engine.search(query.clone()).unwrap();
+        assert_eq!(results.total_hits, 2);
+        assert_eq!(results.results[0].score, results.results[1].score);
+
+        engine.set_exact_match_boost(2.0);
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.results[0].id, "exact");
+        assert!(results.results[0].score > results.results[1].score);
+    }
+
+    #[test]
+    fn test_trie_autocomplete_matches_linear_scan() {
+        let engine = FerrumSearch::new();
+        engine.bulk_import(demo_documents()).unwrap();
+        engine.add_document(make_doc("extra", "Programs", "Programmers write programs")).unwrap();
+        engine.remove_document("extra").unwrap();
+        engine.add_document(make_doc("extra-2", "Search", "search and searching")).unwrap();
+
+        for prefix in ["prog", "se", "web", "fra", "xyz", "programs"] {
+            let mut linear: Vec<String> = engine
+                .inverted_index
+                .read()
+                .unwrap()
+                .keys()
+                .filter(|word| word.starts_with(prefix))
+                .cloned()
+                .collect();
+            linear.sort();
+
+            let mut completions = engine.autocomplete(prefix, usize::MAX);
+            completions.sort();
+            assert_eq!(completions, linear, "prefix {}", prefix);
+        }
+
+        // Most frequent completion first: "search" appears in two documents
+        assert_eq!(engine.autocomplete("sea", 1), vec!["search"]);
+    }
+
+    #[test]
+    fn test_highlight_marks_preserve_original_casing() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Why Rust?", "Memory safety without garbage collection.")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(
+            results.results[0].highlights[0],
+            "Why <mark>Rust</mark>? Memory safety without garbage collection."
+        );
+    }
+
+    #[test]
+    fn test_score_buckets_cover_all_hits() {
+        let engine = FerrumSearch::new();
+        engine.bulk_import(demo_documents()).unwrap();
+        engine.add_document(make_doc("extra", "Rust Search", "rust search engines")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust search programming".to_string(),
+            bucket_scores: Some(vec![0.5, 0.0]),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+
+        assert_eq!(results.buckets.len(), 3);
+        assert_eq!(results.buckets[0].1, 0.0);
+        assert_eq!(results.buckets[1], (0.0, 0.5, results.buckets[1].2));
+        let counted: usize = results.buckets.iter().map(|(_, _, count)| count).sum();
+        assert_eq!(counted, results.total_hits);
+    }
+
+    #[test]
+    fn test_content_hash_ids_deduplicate_reimports() {
+        let engine = FerrumSearch::new();
+        engine.set_id_strategy(IdStrategy::ContentHash);
+
+        let doc = make_doc("", "Rust Programming", "Systems language");
+        engine.bulk_import(vec![doc.clone(), doc.clone()]).unwrap();
+        engine.add_document(doc).unwrap();
+        assert_eq!(engine.get_stats().total_documents, 1);
+
+        engine.add_document(make_doc("", "Rust Programming", "Different content")).unwrap();
+        assert_eq!(engine.get_stats().total_documents, 2);
+
+        engine.set_id_strategy(IdStrategy::Random);
+        engine.add_document(make_doc("", "Rust Programming", "Systems language")).unwrap();
+        assert_eq!(engine.get_stats().total_documents, 3);
+    }
+
+    #[test]
+    fn test_minimum_should_match() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("two", "Rust", "rust safety")).unwrap();
+        engine.add_document(make_doc("three", "Rust", "rust safety speed")).unwrap();
+        engine.add_document(make_doc("one", "Rust", "rust only")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust safety speed".to_string(),
+            minimum_should_match: Some(MinimumShouldMatch::Count(2)),
+            ..Default::default()
+        };
+        let results = engine.search(query.clone()).unwrap();
+        let mut ids: Vec<_> = results.results.iter().map(|r| r.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["three", "two"]);
+
+        let pct = SearchQuery {
+            minimum_should_match: Some("100%".parse().unwrap()),
+            ..query
+        };
+        let results = engine.search(pct).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "three");
+
+        assert_eq!("75%".parse::<MinimumShouldMatch>().unwrap().required(3), 2);
+        assert!("abc".parse::<MinimumShouldMatch>().is_err());
+    }
+
+    #[test]
+    fn test_numeric_metadata_sort_and_range() {
+        let engine = FerrumSearch::new();
+        for (id, price) in [("ten", 10.0), ("two", 2.0), ("nine", 9.0)] {
+            engine.add_document(Document {
+                metadata: HashMap::from([("price".to_string(), price.to_string())]),
+                numeric_metadata: HashMap::from([("price".to_string(), price)]),
+                ..make_doc(id, "Widget", "widget for sale")
+            }).unwrap();
+        }
+        engine.add_document(make_doc("unpriced", "Widget", "widget for sale")).unwrap();
+
+        let query = SearchQuery {
+            query: "widget".to_string(),
+            sort_by: Some("price".to_string()),
+            ..Default::default()
+        };
+        let ids: Vec<String> = engine.search(query.clone()).unwrap().results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["two", "nine", "ten", "unpriced"]);
+
+        let descending = SearchQuery {
+            sort_by: Some("-price".to_string()),
+            ..query.clone()
+        };
+        let ids: Vec<String> = engine.search(descending).unwrap().results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["ten", "nine", "two", "unpriced"]);
+
+        let ranged = SearchQuery {
+            numeric_filters: Some(HashMap::from([(
+                "price".to_string(),
+                NumericRange { min: Some(5.0), max: Some(10.0) },
+            )])),
+            ..query
+        };
+        let ids: Vec<String> = engine.search(ranged).unwrap().results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["nine", "ten"]);
+    }
+
+    #[test]
+    fn test_fully_filtered_query_reports_no_tokens() {
+        let engine = FerrumSearch::new();
+        engine.set_analyzer(Analyzer::default().with_filter(StopWords::english()));
+        engine.bulk_import(demo_documents()).unwrap();
+
+        let query = SearchQuery {
+            query: "a an the".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 0);
+        assert!(results.query_tokens.is_empty());
+
+        let query = SearchQuery {
+            query: "the zebra".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 0);
+        assert_eq!(results.query_tokens, vec!["zebra"]);
+    }
+
+    #[test]
+    fn test_density_highlights_prefer_clustered_terms() {
+        let engine = FerrumSearch::new();
+        let content = format!(
+            "Rust appears alone here. {} Later on, rust and safety appear together.",
+            "Filler sentence without any query words at all. ".repeat(3)
+        );
+        engine.add_document(make_doc("1", "Notes", &content)).unwrap();
+
+        let query = SearchQuery {
+            query: "rust safety".to_string(),
+            ..Default::default()
+        };
+        let first = engine.search(query.clone()).unwrap();
+        assert!(!first.results[0].highlights[0].contains("<mark>safety</mark>"));
+
+        let dense = SearchQuery {
+            highlight_mode: HighlightMode::Density,
+            ..query
+        };
+        let results = engine.search(dense).unwrap();
+        let top = &results.results[0].highlights[0];
+        assert!(top.contains("<mark>rust</mark> and <mark>safety</mark>"));
+    }
+
+    #[test]
+    fn test_rename_metadata_key() {
+        let engine = FerrumSearch::new();
+        for doc in demo_documents() {
+            let mut doc = doc;
+            let category = doc.metadata.remove("category").unwrap();
+            doc.metadata.insert("cat".to_string(), category);
+            engine.add_document(doc).unwrap();
+        }
+        engine.add_document(Document {
+            metadata: HashMap::from([
+                ("cat".to_string(), "old".to_string()),
+                ("category".to_string(), "kept".to_string()),
+            ]),
+            ..make_doc("conflict", "Web Notes", "development notes")
+        }).unwrap();
+
+        assert_eq!(engine.rename_metadata_key("cat", "category").unwrap(), 3);
+        assert!(engine.rename_metadata_key("cat", "cat").is_err());
+
+        let query = SearchQuery {
+            query: "development".to_string(),
+            filters: Some(HashMap::from([("category".to_string(), "web".to_string())])),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "web-dev-trends");
+        assert_eq!(engine.distribution("category")["kept"], 1);
+
+        assert_eq!(engine.rename_metadata_key_overwriting("cat", "category").unwrap(), 1);
+        assert_eq!(engine.distribution("category")["old"], 1);
+    }
+
+    #[test]
+    fn test_empty_documents_are_safe() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("empty", "", "")).unwrap();
+        engine.add_document(make_doc("rust", "Rust", "Systems programming")).unwrap();
+
+        assert_eq!(engine.get_stats().total_documents, 2);
+        assert_eq!(*engine.document_lengths.read().unwrap().get("empty").unwrap(), 1);
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert!(results.results[0].score.is_finite());
+
+        engine.append_content("empty", "now has words").unwrap();
+        assert_eq!(*engine.document_lengths.read().unwrap().get("empty").unwrap(), 3);
+
+        engine.set_empty_document_policy(EmptyDocumentPolicy::Reject);
+        assert_eq!(
+            engine.add_document(make_doc("blank", " ", "")),
+            Err(FerrumError::EmptyDocument("blank".to_string()))
+        );
+        assert_eq!(engine.get_stats().total_documents, 2);
+    }
+
+    #[test]
+    fn test_results_report_matched_terms() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("rust", "Rust", "systems programming")).unwrap();
+        engine.add_document(make_doc("web", "Web", "frontend frameworks")).unwrap();
+        engine.add_document(make_doc("both", "Rust on the web", "wasm")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust web".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let terms_for = |id: &str| {
+            let mut terms = results.results.iter().find(|r| r.id == id).unwrap().matched_terms.clone();
+            terms.sort();
+            terms
+        };
+        assert_eq!(terms_for("rust"), vec!["rust"]);
+        assert_eq!(terms_for("web"), vec!["web"]);
+        assert_eq!(terms_for("both"), vec!["rust", "web"]);
+
+        let fuzzy = SearchQuery {
+            query: "rusty".to_string(),
+            fuzzy: true,
+            ..Default::default()
+        };
+        let results = engine.search(fuzzy).unwrap();
+        assert!(results.results.iter().all(|r| r.matched_terms == vec!["rust"]));
+    }
+
+    #[test]
+    fn test_replace_with_is_atomic_under_load() {
+        fn build(prefix: &str, count: usize) -> FerrumSearch {
+            let engine = FerrumSearch::new();
+            for i in 0..count {
+                engine.add_document(make_doc(&format!("{}-{}", prefix, i), "Alpha", "alpha content")).unwrap();
+            }
+            engine
+        }
+
+        let engine = build("old", 5);
+        let query = SearchQuery {
+            query: "alpha".to_string(),
+            per_page: Some(100),
+            ..Default::default()
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..200 {
+                        let results = engine.search(query.clone()).unwrap();
+                        let prefix = if results.total_hits == 5 { "old-" } else { "new-" };
+                        assert!(results.total_hits == 5 || results.total_hits == 12);
+                        assert_eq!(results.results.len(), results.total_hits);
+                        assert!(results.results.iter().all(|r| r.id.starts_with(prefix)));
+                    }
+                });
+            }
+
+            for i in 0..50 {
+                let (prefix, count) = if i % 2 == 0 { ("new", 12) } else { ("old", 5) };
+                engine.replace_with(build(prefix, count));
+            }
+        });
+
+        assert_eq!(engine.get_stats().total_documents, 5);
+    }
+
+    #[test]
+    fn test_find_by_exact_field() {
+        let engine = FerrumSearch::new();
+        engine.bulk_import(demo_documents()).unwrap();
+
+        let found = engine.find_by_title("Understanding Search Algorithms");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, "search-algorithms");
+
+        assert!(engine.find_by_title("Search Algorithms").is_empty());
+        assert!(engine.find_by_title("understanding search algorithms").is_empty());
+        assert_eq!(engine.find_by_field("title", "understanding search algorithms", true).len(), 1);
+
+        let advanced = engine.find_by_field("difficulty", "advanced", false);
+        assert_eq!(advanced.len(), 1);
+        assert_eq!(advanced[0].id, "search-algorithms");
+    }
+
+    #[test]
+    fn test_fuzzy_only_match_scores_against_matched_term() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Algorithms", "Sorting algorithms explained")).unwrap();
+        engine.add_document(make_doc("2", "Cooking", "Pasta recipes")).unwrap();
+        engine.add_document(make_doc("3", "Gardening", "Growing tomatoes")).unwrap();
+
+        // "algoritms" is not indexed; the score must come from "algorithms"
+        let query = SearchQuery {
+            query: "algoritms".to_string(),
+            fuzzy: true,
+            ..Default::default()
+        };
+        let results = engine.search(query.clone()).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].matched_terms, vec!["algorithms"]);
+        assert!(results.results[0].score > 0.0);
+
+        // Single-document corpus: IDF is negative unless floored
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Algorithms", "Sorting algorithms explained")).unwrap();
+        assert!(engine.search(query.clone()).unwrap().results[0].score < 0.0);
+
+        engine.set_min_term_score(Some(0.01));
+        let results = engine.search(query).unwrap();
+        assert!(results.results[0].score > 0.0);
+    }
+
+    #[test]
+    fn test_merge_from_combines_shards() {
+        let shard_a = FerrumSearch::new();
+        shard_a.add_document(make_doc("rust", "Rust Programming", "Systems language")).unwrap();
+        shard_a.add_document(make_doc("shared", "Old Title", "stale content")).unwrap();
+
+        let shard_b = FerrumSearch::new();
+        shard_b.add_document(make_doc("web", "Web Development", "Frontend frameworks")).unwrap();
+        shard_b.add_document(make_doc("shared", "New Title", "fresh content")).unwrap();
+
+        assert_eq!(shard_a.merge_from(&shard_b).unwrap(), 2);
+        assert!(shard_a.merge_from(&shard_a).is_err());
+        assert_eq!(shard_a.get_stats().total_documents, 3);
+
+        for (term, id) in [("rust", "rust"), ("frontend", "web"), ("fresh", "shared")] {
+            let query = SearchQuery {
+                query: term.to_string(),
+                ..Default::default()
+            };
+            let results = shard_a.search(query).unwrap();
+            assert_eq!(results.total_hits, 1, "term {}", term);
+            assert_eq!(results.results[0].id, id);
+        }
+
+        let stale = SearchQuery {
+            query: "stale".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(shard_a.search(stale).unwrap().total_hits, 0);
+    }
+
+    #[test]
+    fn test_field_analyzer_keeps_short_title_tokens() {
+        let engine = FerrumSearch::new();
+        engine
+            .set_field_analyzer("title", Analyzer::new().with_filter(Lowercase).with_filter(MinLength(2)))
+            .unwrap();
+        assert_eq!(
+            engine.set_field_analyzer("summary", Analyzer::default()),
+            Err(FerrumError::UnknownField("summary".to_string()))
+        );
+
+        engine.add_document(make_doc("title", "UI Patterns", "Layout guidance")).unwrap();
+        engine.add_document(make_doc("content", "Design Notes", "Good ux matters")).unwrap();
+        engine.add_document(make_doc("filler", "Other", "Unrelated text")).unwrap();
+
+        assert_eq!(posting_ids(&engine, "ui"), Some(vec!["title".to_string()]));
+        assert_eq!(posting_ids(&engine, "ux"), None);
+
+        let query = SearchQuery {
+            query: "ui".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "title");
+
+        let query = SearchQuery {
+            query: "ux".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(query).unwrap().total_hits, 0);
+    }
+
+    #[test]
+    fn test_track_total_hits_reports_lower_bound() {
+        let engine = FerrumSearch::new();
+        engine.set_deterministic(true);
+        for i in 0..5 {
+            let content = format!("ownership{}", " rust".repeat(i % 3));
+            engine.add_document(make_doc(&format!("doc{}", i), "Rust notes", &content)).unwrap();
+        }
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            track_total_hits: Some(3),
+            per_page: Some(10),
+            ..Default::default()
+        };
+        let results = engine.search(query.clone()).unwrap();
+        assert_eq!(results.total_hits, 3);
+        assert_eq!(results.total_hits_relation, Relation::GreaterThanOrEqual);
+        assert_eq!(results.results.len(), 3);
+        assert_eq!(results.total_pages, 1);
+        let uncapped = engine
+            .search(SearchQuery { track_total_hits: None, ..query.clone() })
+            .unwrap();
+        let ids = |response: &SearchResponse| -> Vec<String> { response.results.iter().map(|r| r.id.clone()).collect() };
+        assert_eq!(ids(&results), ids(&uncapped)[..3]);
+
+        // Pages past the cap are out of range rather than partially filled
+        let past_cap = engine
+            .search(SearchQuery { per_page: Some(2), page: Some(2), ..query.clone() })
+            .unwrap();
+        assert_eq!(past_cap.results.len(), 1);
+        assert!(!past_cap.out_of_range);
+        let beyond = engine
+            .search(SearchQuery { per_page: Some(2), page: Some(3), ..query })
+            .unwrap();
+        assert!(beyond.results.is_empty());
+        assert!(beyond.out_of_range);
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            track_total_hits: Some(10),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 5);
+        assert_eq!(results.total_hits_relation, Relation::Equal);
+    }
+
+    #[test]
+    fn test_search_defaults_apply_to_unset_options() {
+        let engine = FerrumSearch::new();
+        for i in 0..30 {
+            let content = if i % 2 == 0 { "rust async" } else { "rust only" };
+            engine.add_document(make_doc(&format!("doc{}", i), "Notes", content)).unwrap();
+        }
+        engine.set_search_defaults(SearchDefaults {
+            per_page: 25,
+            operator: QueryOperator::And,
+            highlight: false,
+            ..Default::default()
+        });
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.per_page, 25);
+        assert_eq!(results.results.len(), 25);
+        assert!(results.results[0].highlights.is_empty());
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            per_page: Some(10),
+            highlight: Some(true),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.per_page, 10);
+        assert!(!results.results[0].highlights.is_empty());
+
+        let query = SearchQuery {
+            query: "rust async".to_string(),
+            per_page: Some(50),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(query).unwrap().total_hits, 15);
+
+        let query = SearchQuery {
+            query: "rust async".to_string(),
+            per_page: Some(50),
+            operator: Some(QueryOperator::Or),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(query).unwrap().total_hits, 30);
+    }
+
+    #[test]
+    fn test_highlights_escape_document_markup() {
+        let engine = FerrumSearch::new();
+        engine
+            .add_document(make_doc("html", "Markup", "Use <b>bold</b> & \"quoted\" rust tags"))
+            .unwrap();
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let snippet = &results.results[0].highlights[0];
+        assert!(snippet.contains("&lt;b&gt;bold&lt;/b&gt; &amp; &quot;quoted&quot;"), "{}", snippet);
+        assert!(snippet.contains("<mark>rust</mark>"), "{}", snippet);
+        assert!(!snippet.contains("<b>"));
+    }
+
+    #[test]
+    fn test_search_fields_restricts_scoring() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("titled", "Rust Guide", "Ownership and borrowing")).unwrap();
+        engine.add_document(make_doc("body", "Language Notes", "Rust has traits")).unwrap();
+        engine.add_document(make_doc("filler", "Other", "Unrelated text")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            search_fields: Some(vec!["content".to_string()]),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "body");
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            search_fields: Some(vec!["title".to_string()]),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "titled");
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            search_fields: Some(vec!["summary".to_string()]),
+            ..Default::default()
+        };
+        assert!(engine.search(query).is_err());
+
+        engine.set_search_defaults(SearchDefaults {
+            search_fields: Some(vec!["content".to_string()]),
+            ..Default::default()
+        });
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(query).unwrap().total_hits, 1);
+    }
+
+    #[test]
+    fn test_autocomplete_batch() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Berlin Travel", "Alice visits museums")).unwrap();
+        engine.add_document(make_doc("2", "Boston Guide", "Alice and Bob tagging")).unwrap();
+
+        let completions = engine.autocomplete_batch(&["ber", "ali", "tag"], 5);
+        assert_eq!(completions.len(), 3);
+        assert_eq!(completions["ber"], vec!["berlin".to_string()]);
+        assert_eq!(completions["ali"], vec!["alice".to_string()]);
+        assert_eq!(completions["tag"], vec!["tagging".to_string()]);
+        assert_eq!(completions["ber"], engine.autocomplete("ber", 5));
+    }
+
+    #[test]
+    fn test_recompute_avg_doc_len_matches_scratch_average() {
+        let engine = FerrumSearch::new();
+        for i in 0..200 {
+            let content = "word ".repeat(i % 17 + 1);
+            engine.add_document(make_doc(&format!("doc{}", i), "Title", &content)).unwrap();
+        }
+        for i in (0..200).step_by(3) {
+            engine.remove_document(&format!("doc{}", i)).unwrap();
+        }
+        for i in (1..200).step_by(7) {
+            engine.add_document(make_doc(&format!("doc{}", i), "Retitled", "short")).unwrap();
+        }
+        engine.append_content("doc2", "more words appended here").unwrap();
+
+        let lengths = engine.document_lengths.read().unwrap().clone();
+        assert_eq!(lengths.len(), *engine.total_documents.read().unwrap());
+        let scratch = lengths.values().sum::<usize>() as f64 / lengths.len() as f64;
+
+        assert!((*engine.avg_doc_len.read().unwrap() - scratch).abs() < 1e-6);
+        engine.recompute_avg_doc_len();
+        assert!((*engine.avg_doc_len.read().unwrap() - scratch).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_pagination_flags() {
+        let engine = FerrumSearch::new();
+        for i in 0..7 {
+            engine.add_document(make_doc(&format!("doc{}", i), "Rust", "notes")).unwrap();
+        }
+
+        let page = |page| {
+            let query = SearchQuery {
+                query: "rust".to_string(),
+                page: Some(page),
+                per_page: Some(3),
+                ..Default::default()
+            };
+            engine.search(query).unwrap()
+        };
+
+        let first = page(1);
+        assert_eq!(first.total_pages, 3);
+        assert!(first.has_next && !first.has_prev);
+        assert_eq!((first.next_page, first.prev_page), (Some(2), None));
+
+        let middle = page(2);
+        assert!(middle.has_next && middle.has_prev);
+        assert_eq!((middle.next_page, middle.prev_page), (Some(3), Some(1)));
+
+        let last = page(3);
+        assert!(!last.has_next && last.has_prev);
+        assert_eq!((last.next_page, last.prev_page), (None, Some(2)));
+        assert_eq!(last.results.len(), 1);
+    }
+
+    #[test]
+    fn test_estimate_cost_matches_scored_candidates() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "systems language")).unwrap();
+        engine.add_document(make_doc("2", "Rusty", "old metal")).unwrap();
+        engine.add_document(make_doc("3", "Python", "systems scripting")).unwrap();
+        engine.add_document(make_doc("4", "Go", "simple language")).unwrap();
+
+        for (text, fuzzy, terms) in [("rust", false, 1), ("rust", true, 2), ("systems language", false, 2)] {
+            let query = SearchQuery {
+                query: text.to_string(),
+                fuzzy,
+                ..Default::default()
+            };
+            let cost = engine.estimate_cost(&query);
+            assert_eq!(cost.expanded_terms, terms, "query {}", text);
+            assert_eq!(cost.candidate_docs, engine.search(query).unwrap().total_hits, "query {}", text);
+        }
+
+        let none = SearchQuery {
+            query: "zebra".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.estimate_cost(&none), QueryCost { candidate_docs: 0, expanded_terms: 1 });
+    }
+
+    #[test]
+    fn test_include_and_exclude_filters_combine() {
+        let engine = FerrumSearch::new();
+        for (id, category, year) in [("a", "web", "2023"), ("b", "web", "2024"), ("c", "systems", "2024"), ("d", "web", "2022")] {
+            let mut doc = make_doc(id, "Framework notes", "framework");
+            doc.metadata.insert("category".to_string(), category.to_string());
+            doc.metadata.insert("year".to_string(), year.to_string());
+            engine.add_document(doc).unwrap();
+        }
+        engine.add_document(make_doc("filler", "Other", "unrelated")).unwrap();
+
+        let query = SearchQuery {
+            query: "framework".to_string(),
+            filters: Some(HashMap::from([("category".to_string(), "web".to_string())])),
+            exclude_filters: Some(HashMap::from([("year".to_string(), "2023".to_string())])),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let mut ids: Vec<_> = results.results.iter().map(|r| r.id.as_str()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec!["b", "d"]);
+
+        let query = SearchQuery {
+            query: "framework".to_string(),
+            exclude_filters: Some(HashMap::from([("category".to_string(), "web".to_string())])),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "c");
+    }
+
+    #[test]
+    fn test_default_engine_is_empty_until_insert() {
+        let engine = FerrumSearch::default();
+        assert!(engine.is_empty());
+
+        engine.add_document(make_doc("1", "Rust", "notes")).unwrap();
+        assert!(!engine.is_empty());
+
+        engine.remove_document("1").unwrap();
+        assert!(engine.is_empty());
+    }
+
+    #[test]
+    fn test_sentence_window_highlights() {
+        let engine = FerrumSearch::new();
+        engine
+            .add_document(make_doc(
+                "doc",
+                "Guide",
+                "Intro text here. Rust guarantees memory safety without a collector! Other notes follow.",
+            ))
+            .unwrap();
+
+        let query = SearchQuery {
+            query: "memory".to_string(),
+            highlight_mode: HighlightMode::SentenceWindow,
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(
+            results.results[0].highlights,
+            vec!["Rust guarantees <mark>memory</mark> safety without a collector!".to_string()]
+        );
+
+        let query = SearchQuery {
+            query: "guide".to_string(),
+            highlight_mode: HighlightMode::SentenceWindow,
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.results[0].highlights, vec!["<mark>Guide</mark>".to_string()]);
+    }
+
+    #[test]
+    fn test_typed_errors() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "notes")).unwrap();
+
+        for (page, per_page) in [(Some(0), Some(10)), (Some(1), Some(0))] {
+            let query = SearchQuery {
+                query: "rust".to_string(),
+                page,
+                per_page,
+                ..Default::default()
+            };
+            assert_eq!(engine.search(query).unwrap_err(), FerrumError::InvalidPagination);
+        }
+
+        assert!(matches!(
+            engine.rename_metadata_key("", "to"),
+            Err(FerrumError::InvalidArgument(_))
+        ));
+        assert!(matches!(engine.merge_from(&engine), Err(FerrumError::InvalidArgument(_))));
+        assert!(matches!(
+            engine.import_file(Path::new("/nonexistent/ferrum.ndjson")),
+            Err(FerrumError::Io(_))
+        ));
+
+        let error = FerrumError::NotFound("42".to_string());
+        assert_eq!(error.to_string(), "Document '42' not found");
+        let boxed: Box<dyn std::error::Error> = Box::new(error);
+        assert!(boxed.source().is_none());
+    }
+
+    #[test]
+    fn test_required_terms() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("both", "Rust", "performance tuning")).unwrap();
+        engine.add_document(make_doc("rust", "Rust", "ownership model")).unwrap();
+        engine.add_document(make_doc("perf", "Python", "performance tuning")).unwrap();
+        add_fillers(&engine, 3);
+
+        let query = SearchQuery {
+            query: "+rust performance".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let ids: Vec<_> = results.results.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["both", "rust"]);
+
+        let query = SearchQuery {
+            query: "+rust performance -ownership".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "both");
+    }
+
+    #[test]
+    fn test_query_cache_serves_repeats_until_mutation() {
+        let engine = FerrumSearch::new();
+        engine.set_query_cache(8);
+        engine.add_document(make_doc("1", "Rust", "ownership")).unwrap();
+        engine.add_document(make_doc("filler", "Other", "unrelated")).unwrap();
+        let hits = || engine.query_cache.read().unwrap().as_ref().unwrap().hits;
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(query.clone()).unwrap().total_hits, 1);
+        assert_eq!(hits(), 0);
+
+        let respaced = SearchQuery {
+            query: "  rust ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(respaced).unwrap().total_hits, 1);
+        assert_eq!(hits(), 1);
+
+        engine.add_document(make_doc("2", "Rust again", "borrowing")).unwrap();
+        assert_eq!(engine.search(query.clone()).unwrap().total_hits, 2);
+        assert_eq!(hits(), 1);
+
+        engine.search(query).unwrap();
+        assert_eq!(hits(), 2);
+    }
+
+    #[test]
+    fn test_index_health_tracks_deletions() {
+        let engine = FerrumSearch::new();
+        for i in 0..4 {
+            engine.add_document(make_doc(&format!("doc{}", i), "Rust", &format!("topic{}", i))).unwrap();
+        }
+
+        engine.optimize();
+
+        let health = engine.index_health();
+        assert_eq!(health.deleted_ratio, 0.0);
+        assert_eq!(health.empty_postings, 0);
+        assert_eq!(health.vocabulary_size, 5);
+        assert!((health.avg_posting_len - 8.0 / 5.0).abs() < 1e-9);
+
+        // Each removal frees its document's handle until a new document reuses it
+        engine.remove_document("doc0").unwrap();
+        let after_one = engine.index_health();
+        assert!((after_one.deleted_ratio - 0.25).abs() < 1e-9);
+        assert_eq!(after_one.empty_postings, 1);
+
+        engine.remove_document("doc1").unwrap();
+        let after_two = engine.index_health();
+        assert!(after_two.deleted_ratio > after_one.deleted_ratio);
+        assert_eq!(after_two.empty_postings, 2);
+        engine.add_document(make_doc("doc4", "Rust", "topic4")).unwrap();
+        assert_eq!(engine.index_health().empty_postings, 1);
+
+        engine.optimize();
+        let health = engine.index_health();
+        assert_eq!(health.deleted_ratio, 0.0);
+        assert_eq!(health.empty_postings, 0);
+        assert_eq!(posting_ids(&engine, "rust").unwrap(), vec!["doc2", "doc3", "doc4"]);
+        assert_eq!(posting_ids(&engine, "topic4").unwrap(), vec!["doc4"]);
+        let by_title = SearchQuery {
+            query: "rust".to_string(),
+            search_fields: Some(vec!["title".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(by_title).unwrap().total_hits, 3);
+    }
+
+    #[test]
+    fn test_popular_queries_rank_by_frequency_then_recency() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "notes")).unwrap();
+        for text in ["rust async", "Rust  Async", "rust macros", "python", "rust async", "rust traits"] {
+            let query = SearchQuery {
+                query: text.to_string(),
+                ..Default::default()
+            };
+            engine.search(query).unwrap();
+        }
+
+        assert_eq!(
+            engine.popular_queries("ru", 3),
+            vec!["rust async".to_string(), "rust traits".to_string(), "rust macros".to_string()]
+        );
+        assert_eq!(engine.popular_queries("PY", 5), vec!["python".to_string()]);
+
+        engine.set_query_log_capacity(2);
+        assert_eq!(engine.popular_queries("rust", 5), vec!["rust traits".to_string(), "rust async".to_string()]);
+    }
+
+    #[test]
+    fn test_kept_symbols_preserve_technical_terms() {
+        let strict = Analyzer::default().with_kept_symbols(".+-");
+        assert_eq!(
+            strict.analyze("Learn C++, node.js and .NET for covid-19 dashboards."),
+            vec!["learn", "c++", "node.js", "and", ".net", "for", "covid-19", "dashboards"]
+        );
+        assert_eq!(strict.analyze("a - b"), Vec::<String>::new());
+
+        let engine = FerrumSearch::new();
+        engine.set_analyzer(Analyzer::default().with_kept_symbols("."));
+        engine.add_document(make_doc("node", "Runtimes", "Serving apps with node.js daily.")).unwrap();
+        engine.add_document(make_doc("filler", "Other", "Unrelated text")).unwrap();
+        engine.add_document(make_doc("filler2", "More", "Nothing here")).unwrap();
+        assert!(engine.inverted_index.read().unwrap().contains_key("node.js"));
+        assert!(engine.inverted_index.read().unwrap().contains_key("daily"));
+
+        let query = SearchQuery {
+            query: "node.js".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].matched_terms, vec!["node.js".to_string()]);
+
+        assert_eq!(Analyzer::default().analyze("node.js"), vec!["nodejs"]);
+    }
+
+    #[test]
+    fn test_indexed_at_records_engine_time() {
+        let engine = FerrumSearch::new();
+        let before = now_secs();
+        engine.add_document(Document { timestamp: 42, ..make_doc("1", "Rust", "notes") }).unwrap();
+        let after = now_secs();
+
+        let indexed_at = engine.indexed_at("1").unwrap();
+        assert!(indexed_at >= before && indexed_at <= after);
+        assert_eq!(engine.documents.read().unwrap()["1"].timestamp, 42);
+        assert_eq!(engine.documents_indexed_before(after + 1), vec!["1".to_string()]);
+        assert!(engine.documents_indexed_before(before).is_empty());
+
+        engine.remove_document("1").unwrap();
+        assert_eq!(engine.indexed_at("1"), None);
+    }
+
+    #[test]
+    fn test_score_stats_cover_all_matches() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "rust rust memory")).unwrap();
+        engine.add_document(make_doc("2", "Rust", "memory safety and more words here")).unwrap();
+        engine.add_document(make_doc("3", "Rust", "memory")).unwrap();
+        add_fillers(&engine, 4);
+
+        let query = SearchQuery {
+            query: "rust memory".to_string(),
+            per_page: Some(1),
+            include_score_stats: true,
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let stats = results.score_stats.unwrap();
+        assert_eq!(stats.max, results.results[0].score);
+        assert!(stats.min <= stats.mean && stats.mean <= stats.max);
+        assert!(stats.std_dev >= 0.0);
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        assert!(engine.search(query).unwrap().score_stats.is_none());
+    }
+
+    #[test]
+    fn test_cjk_bigram_segmentation() {
+        let analyzer = Analyzer::default().with_cjk(CjkSegmentation::Bigrams);
+        assert_eq!(analyzer.analyze("東京の天気 Rust入門"), vec!["東京", "京の", "の天", "天気", "rust", "入門"]);
+        let unigrams = Analyzer::new().with_cjk(CjkSegmentation::Unigrams);
+        assert_eq!(unigrams.analyze("天気"), vec!["天", "気"]);
+
+        let engine = FerrumSearch::new();
+        engine.set_analyzer(Analyzer::default().with_cjk(CjkSegmentation::Bigrams));
+        engine.add_document(make_doc("tokyo", "旅行", "東京都の天気予報です")).unwrap();
+        engine.add_document(make_doc("osaka", "旅行", "大阪のグルメ情報")).unwrap();
+        engine.add_document(make_doc("filler", "Other", "unrelated text")).unwrap();
+
+        let query = SearchQuery {
+            query: "天気予報".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        assert_eq!(results.results[0].id, "tokyo");
+    }
+
+    #[test]
+    fn test_best_snippet_picks_densest_window() {
+        let engine = FerrumSearch::new();
+        let content = format!(
+            "Rust appears alone here. {} Later the memory safety of rust shows up together.",
+            "Padding words fill the middle of this document. ".repeat(4)
+        );
+        let doc = make_doc("doc", "Notes", &content);
+        engine.add_document(doc.clone()).unwrap();
+        engine.add_document(make_doc("filler", "Other", "unrelated")).unwrap();
+
+        let tokens = vec!["rust".to_string(), "memory".to_string(), "safety".to_string()];
+        let snippet = engine.best_snippet(&doc, &tokens).unwrap();
+        assert!(snippet.contains("<mark>memory</mark> <mark>safety</mark> of <mark>rust</mark>"), "{}", snippet);
+        assert!(!snippet.contains("alone"));
+        assert_eq!(engine.best_snippet(&doc, &["zebra".to_string()]), None);
+
+        let query = SearchQuery {
+            query: "rust memory safety".to_string(),
+            best_snippet_only: true,
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.results[0].highlights, vec![snippet]);
+    }
+
+    #[test]
+    fn test_metadata_presence_filters() {
+        let engine = FerrumSearch::new();
+        for doc in demo_documents() {
+            engine.add_document(doc).unwrap();
+        }
+        let ids = |query: SearchQuery| {
+            let mut ids: Vec<String> = engine.search(query).unwrap().results.into_iter().map(|r| r.id).collect();
+            ids.sort_unstable();
+            ids
+        };
+
+        let with_difficulty = SearchQuery {
+            query: "rust web search".to_string(),
+            has_fields: Some(vec!["difficulty".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(ids(with_difficulty), vec!["rust-guide", "search-algorithms"]);
+
+        let without_year = SearchQuery {
+            query: "rust web search".to_string(),
+            missing_fields: Some(vec!["year".to_string()]),
+            filters: Some(HashMap::from([("category".to_string(), "programming".to_string())])),
+            ..Default::default()
+        };
+        assert_eq!(ids(without_year), vec!["rust-guide"]);
+
+        let without_difficulty = SearchQuery {
+            query: "rust web search".to_string(),
+            missing_fields: Some(vec!["difficulty".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(ids(without_difficulty), vec!["web-dev-trends"]);
+    }
+
+    #[test]
+    fn test_field_combine_changes_term_frequency() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("both", "Rust Handbook", "rust ownership and borrowing")).unwrap();
+        add_fillers(&engine, 4);
+        let score = || {
+            let query = SearchQuery {
+                query: "rust".to_string(),
+                ..Default::default()
+            };
+            engine.search(query).unwrap().results[0].score
+        };
+
+        let summed = score();
+        engine.set_field_combine(FieldCombine::Max);
+        let maxed = score();
+        assert!(maxed < summed, "max {} vs sum {}", maxed, summed);
+
+        engine.set_field_combine(FieldCombine::WeightedSum { title: 1.0, content: 1.0 });
+        assert!((score() - summed).abs() < 1e-6);
+        engine.set_field_combine(FieldCombine::WeightedSum { title: 3.0, content: 1.0 });
+        assert!(score() > summed);
+    }
+
+    #[test]
+    fn test_deterministic_mode_reproduces_responses() {
+        let engine = FerrumSearch::new();
+        engine.set_deterministic(true);
+        engine.set_query_cache(0);
+        for i in 0..6 {
+            let mut doc = make_doc(&format!("doc{}", i), "Rust notes", "identical content");
+            doc.metadata.insert("k".to_string(), "v".to_string());
+            engine.add_document(doc).unwrap();
+        }
+        engine.add_document(make_doc("filler", "Other", "unrelated")).unwrap();
+        assert_eq!(engine.indexed_at("doc0"), Some(0));
+        assert_eq!(engine.indexed_at("doc5"), Some(5));
+
+        let run = || {
+            let query = SearchQuery {
+                query: "rust".to_string(),
+                ..Default::default()
+            };
+            serde_json::to_string(&engine.search(query).unwrap()).unwrap()
+        };
+        let first = run();
+        assert_eq!(first, run());
+        assert!(first.contains("\"query_time_ms\":0"));
+
+        // Searches and stats read the logical clock without advancing it
+        engine.get_stats();
+        engine.add_document(make_doc("next", "Next", "content")).unwrap();
+        assert_eq!(engine.indexed_at("next"), Some(7));
+        assert_eq!(engine.get_stats().last_updated, 8);
+
+        engine.set_clock(Box::new(|| 1_700_000_000));
+        engine.add_document(make_doc("late", "Late", "content")).unwrap();
+        assert_eq!(engine.indexed_at("late"), Some(1_700_000_000));
+        assert_eq!(engine.get_stats().last_updated, 1_700_000_000);
+
+        // Leaving deterministic mode keeps the injected clock
+        engine.set_deterministic(false);
+        engine.add_document(make_doc("later", "Later", "content")).unwrap();
+        assert_eq!(engine.indexed_at("later"), Some(1_700_000_000));
+    }
+
+    #[test]
+    fn test_multi_key_sort() {
+        let engine = FerrumSearch::new();
+        for (id, category, timestamp) in [("a", "web", 10), ("b", "systems", 5), ("c", "web", 30), ("d", "systems", 20)] {
+            let mut doc = Document { timestamp, ..make_doc(id, "Rust notes", "rust") };
+            doc.metadata.insert("category".to_string(), category.to_string());
+            engine.add_document(doc).unwrap();
+        }
+        engine.add_document(Document { timestamp: 99, ..make_doc("e", "Rust notes", "rust") }).unwrap();
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            sort: Some(vec![
+                ("category".to_string(), SortOrder::Asc),
+                ("timestamp".to_string(), SortOrder::Desc),
+            ]),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let ids: Vec<_> = results.results.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["d", "b", "c", "a", "e"]);
+    }
+
+    #[test]
+    fn test_validate_query_rejects_each_malformed_condition() {
+        let engine = FerrumSearch::new();
+        let base = || SearchQuery {
+            query: "rust".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(engine.validate_query(&base()), Ok(()));
+
+        let zero_page = SearchQuery { page: Some(0), ..base() };
+        assert_eq!(engine.validate_query(&zero_page), Err(FerrumError::InvalidPagination));
+        let huge_page = SearchQuery { per_page: Some(MAX_PER_PAGE + 1), ..base() };
+        assert_eq!(engine.validate_query(&huge_page), Err(FerrumError::InvalidPagination));
+
+        let empty_key = SearchQuery {
+            filters: Some(HashMap::from([(String::new(), "web".to_string())])),
+            ..base()
+        };
+        assert!(matches!(engine.validate_query(&empty_key), Err(FerrumError::InvalidQuery(_))));
+        let empty_sort = SearchQuery { sort_by: Some("-".to_string()), ..base() };
+        assert!(matches!(engine.validate_query(&empty_sort), Err(FerrumError::InvalidQuery(_))));
+
+        let unknown_field = SearchQuery { search_fields: Some(vec!["body".to_string()]), ..base() };
+        assert_eq!(engine.validate_query(&unknown_field), Err(FerrumError::UnknownField("body".to_string())));
+
+        let inverted = SearchQuery {
+            numeric_filters: Some(HashMap::from([(
+                "price".to_string(),
+                NumericRange { min: Some(10.0), max: Some(1.0) },
+            )])),
+            ..base()
+        };
+        assert!(matches!(engine.validate_query(&inverted), Err(FerrumError::InvalidQuery(_))));
+
+        let contradictory = SearchQuery { query: "+rust -Rust".to_string(), ..base() };
+        assert_eq!(
+            engine.validate_query(&contradictory),
+            Err(FerrumError::InvalidQuery("'rust' is both required and excluded".to_string()))
+        );
+        assert!(engine.search(contradictory).is_err());
+    }
+
+    #[test]
+    fn test_acl_filters_unauthorized_documents() {
+        let engine = FerrumSearch::new();
+        engine.add_document(Document { acl: vec!["finance".to_string()], ..make_doc("budget", "Rust budget", "rust") }).unwrap();
+        engine.add_document(Document { acl: vec!["eng".to_string()], ..make_doc("design", "Rust design", "rust") }).unwrap();
+        engine.add_document(make_doc("public", "Rust intro", "rust")).unwrap();
+        add_fillers(&engine, 3);
+
+        let ids_for = |groups: Option<Vec<&str>>| {
+            let query = SearchQuery {
+                query: "rust".to_string(),
+                allowed_acls: groups.map(|g| g.into_iter().map(String::from).collect()),
+                ..Default::default()
+            };
+            let results = engine.search(query).unwrap();
+            let mut ids: Vec<String> = results.results.into_iter().map(|r| r.id).collect();
+            ids.sort_unstable();
+            assert_eq!(ids.len(), results.total_hits);
+            ids
+        };
+
+        assert_eq!(ids_for(Some(vec!["eng"])), vec!["design", "public"]);
+        assert_eq!(ids_for(Some(vec![])), vec!["public"]);
+        assert_eq!(ids_for(None).len(), 3);
+    }
+
+    #[test]
+    fn test_search_multi_merges_scores() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("rust", "Rust", "ownership model")).unwrap();
+        engine.add_document(make_doc("both", "Rust Python", "ownership")).unwrap();
+        engine.add_document(make_doc("python", "Python", "scripting language")).unwrap();
+        add_fillers(&engine, 4);
+        let queries = || {
+            vec![
+                SearchQuery { query: "rust".to_string(), ..Default::default() },
+                SearchQuery { query: "python".to_string(), ..Default::default() },
+            ]
+        };
+
+        let summed = engine.search_multi(queries(), CombineMode::Sum).unwrap();
+        let ids: Vec<_> = summed.results.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(summed.total_hits, 3);
+        assert_eq!(ids[0], "both");
+        assert!(ids.contains(&"rust") && ids.contains(&"python"));
+        assert_eq!(summed.results[0].matched_terms, vec!["rust".to_string(), "python".to_string()]);
+        assert_eq!(summed.query_tokens, vec!["rust".to_string(), "python".to_string()]);
+
+        let maxed = engine.search_multi(queries(), CombineMode::Max).unwrap();
+        let both = |r: &SearchResponse| r.results.iter().find(|r| r.id == "both").unwrap().score;
+        assert!(both(&maxed) < both(&summed));
+        assert!(engine.search_multi(vec![], CombineMode::Sum).is_err());
+    }
+
+    #[test]
+    fn test_purge_older_than() {
+        let engine = FerrumSearch::new();
+        for doc in demo_documents() {
+            engine.add_document(doc).unwrap();
+        }
+
+        // Removes rust-guide (2022-01-01) and search-algorithms (2023-01-01)
+        assert_eq!(engine.purge_older_than(1_700_000_000).unwrap(), 2);
+        let mut remaining: Vec<String> = engine.snapshot_documents().into_iter().map(|d| d.id).collect();
+        remaining.sort_unstable();
+        assert_eq!(remaining, vec!["web-dev-trends".to_string()]);
+        assert!(!engine.inverted_index.read().unwrap().contains_key("rust"));
+        assert_eq!(engine.purge_older_than(1_700_000_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_boost_filters_prefer_matching_documents() {
+        let engine = FerrumSearch::new();
+        for (id, category) in [("basic1", "basic"), ("premium", "premium"), ("basic2", "basic")] {
+            let mut doc = make_doc(id, "Rust course", "rust lessons");
+            doc.metadata.insert("category".to_string(), category.to_string());
+            engine.add_document(doc).unwrap();
+        }
+        add_fillers(&engine, 4);
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            boost_filters: Some(vec![(HashMap::from([("category".to_string(), "premium".to_string())]), 2.0)]),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 3);
+        assert_eq!(results.results[0].id, "premium");
+        assert!((results.results[0].score - 2.0 * results.results[1].score).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_update_only_touches_changed_postings() {
+        let engine = FerrumSearch::new();
+        let words: Vec<String> = (0..200).map(|i| format!("word{:03}", i)).collect();
+        engine.add_document(make_doc("big", "Large", &words.join(" "))).unwrap();
+        engine.add_document(make_doc("other", "Large", "word000 word199")).unwrap();
+
+        let mut edited = words.clone();
+        edited[100] = "replacement".to_string();
+        engine.add_document(make_doc("big", "Large", &edited.join(" "))).unwrap();
+
+        // Unchanged terms keep their original posting order instead of being re-appended
+        assert_eq!(posting_ids(&engine, "word000").unwrap(), vec!["big".to_string(), "other".to_string()]);
+        assert_eq!(posting_ids(&engine, "large").unwrap(), vec!["big".to_string(), "other".to_string()]);
+        assert_eq!(posting_ids(&engine, "word100"), None);
+        assert_eq!(posting_ids(&engine, "replacement").unwrap(), vec!["big".to_string()]);
+
+        assert_eq!(engine.autocomplete("word10", 20).len(), 9);
+        let frequencies = engine.word_frequencies.read().unwrap();
+        assert!(frequencies["big"].contains_key("replacement"));
+        assert!(!frequencies["big"].contains_key("word100"));
+    }
+
+    #[test]
+    fn test_structured_query_from_json() {
+        let engine = FerrumSearch::new();
+        for (id, content, tier, category) in [
+            ("plain", "rust async runtime", "free", "web"),
+            ("premium", "rust async runtime", "premium", "web"),
+            ("legacy", "rust legacy async code", "free", "web"),
+            ("sync", "rust threads", "free", "web"),
+            ("systems", "rust async kernels", "free", "systems"),
+        ] {
+            let mut doc = make_doc(id, "Notes", content);
+            doc.metadata.insert("tier".to_string(), tier.to_string());
+            doc.metadata.insert("category".to_string(), category.to_string());
+            engine.add_document(doc).unwrap();
+        }
+        add_fillers(&engine, 4);
+
+        let json = r#"{
+            "bool": {"must": ["rust"], "should": ["async"], "must_not": ["legacy"]},
+            "filters": {"category": "web"},
+            "boosts": [{"filter": {"tier": "premium"}, "factor": 3.0}]
+        }"#;
+        let structured: StructuredQuery = serde_json::from_str(json).unwrap();
+        let results = engine.search_structured(structured).unwrap();
+        let ids: Vec<_> = results.results.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["premium", "plain", "sync"]);
+    }
+
+    #[test]
+    fn test_searchable_metadata_highlights() {
+        let engine = FerrumSearch::new();
+        engine.set_searchable_metadata(vec!["summary".to_string()]);
+        let mut doc = make_doc("book", "Rust Book", "Chapters about ownership");
+        doc.metadata.insert("summary".to_string(), "A gentle primer for newcomers".to_string());
+        doc.metadata.insert("notes".to_string(), "primer in unindexed notes".to_string());
+        engine.add_document(doc).unwrap();
+        engine.add_document(make_doc("filler", "Other", "unrelated")).unwrap();
+        engine.add_document(make_doc("filler2", "More", "nothing")).unwrap();
+
+        let query = SearchQuery {
+            query: "primer".to_string(),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 1);
+        let result = &results.results[0];
+        assert_eq!(
+            result.field_highlights.get("summary"),
+            Some(&vec!["A gentle <mark>primer</mark> for newcomers".to_string()])
+        );
+        assert!(!result.field_highlights.contains_key("notes"));
+    }
+
+    #[test]
+    fn test_bm25l_narrows_long_document_penalty() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("short", "Short", "rust guide")).unwrap();
+        let padding = vec!["padding"; 40].join(" ");
+        engine
+            .add_document(make_doc("long", "Long", &format!("rust rust {}", padding)))
+            .unwrap();
+        add_fillers(&engine, 4);
+
+        let ratio = |saturation: TfSaturation| {
+            engine.set_tf_saturation(saturation);
+            let results = engine
+                .search(SearchQuery { query: "rust".to_string(), ..Default::default() })
+                .unwrap();
+            let ids: Vec<_> = results.results.iter().map(|r| r.id.as_str()).collect();
+            assert_eq!(ids, vec!["short", "long"]);
+            results.results[1].score / results.results[0].score
+        };
+
+        let bm25 = ratio(TfSaturation::Bm25);
+        let bm25l = ratio(TfSaturation::Bm25L);
+        assert!(bm25l > bm25, "BM25L should penalize the long doc less: {} vs {}", bm25l, bm25);
+    }
+
+    #[test]
+    fn test_replica_follows_change_stream() {
+        let primary = FerrumSearch::new();
+        let replica = FerrumSearch::new();
+        let changes = primary.subscribe_changes();
+
+        primary.add_document(make_doc("1", "Rust", "Systems programming")).unwrap();
+        primary.add_document(make_doc("2", "Web", "Frameworks and programming")).unwrap();
+        primary.add_document(make_doc("3", "Go", "Concurrency")).unwrap();
+        primary.append_content("3", "and programming").unwrap();
+        primary.remove_document("1").unwrap();
+
+        for event in changes.try_iter() {
+            replica.apply_change(event).unwrap();
+        }
+
+        let search = |engine: &FerrumSearch| {
+            let results = engine
+                .search(SearchQuery { query: "programming".to_string(), ..Default::default() })
+                .unwrap();
+            results.results.into_iter().map(|r| (r.id, r.score)).collect::<Vec<_>>()
+        };
+        assert_eq!(search(&primary), search(&replica));
+        assert_eq!(replica.get_stats().total_documents, 2);
+    }
+
+    #[test]
+    fn test_only_ids_restricts_candidates() {
+        let engine = FerrumSearch::new();
+        for id in ["a", "b", "c"] {
+            engine.add_document(make_doc(id, "Rust", "ownership and borrowing")).unwrap();
+        }
+        add_fillers(&engine, 4);
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            only_ids: Some(vec!["c".to_string(), "a".to_string(), "filler0".to_string()]),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let mut ids: Vec<_> = results.results.iter().map(|r| r.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "c"]);
+        assert_eq!(results.total_hits, 2);
+    }
+
+    #[test]
+    fn test_joined_highlight_output() {
+        let engine = FerrumSearch::new();
+        let filler = vec!["lorem"; 30].join(" ");
+        engine
+            .add_document(make_doc("doc", "Guide", &format!("rust basics {} async patterns", filler)))
+            .unwrap();
+        engine.add_document(make_doc("other", "Other", "unrelated")).unwrap();
+        engine.add_document(make_doc("more", "More", "nothing")).unwrap();
+
+        let query = SearchQuery {
+            query: "rust async".to_string(),
+            highlight: Some(true),
+            highlight_output: HighlightOutput::Joined(" … ".to_string()),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let result = &results.results[0];
+        assert_eq!(result.highlights.len(), 2);
+        assert_eq!(result.joined_highlight, Some(result.highlights.join(" … ")));
+        assert!(result.joined_highlight.as_ref().unwrap().contains(" … "));
+    }
+
+    #[test]
+    fn test_suggest_titles_matches_whole_query() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("web", "Modern Web Development Trends 2024", "frameworks")).unwrap();
+        engine.add_document(make_doc("ml", "Machine Learning Fundamentals", "models")).unwrap();
+        engine.add_document(make_doc("rust", "The Rust Programming Language Guide", "ownership")).unwrap();
+
+        assert_eq!(
+            engine.suggest_titles("modrn web", 1, None),
+            vec!["Modern Web Development Trends 2024".to_string()]
+        );
+        assert_eq!(engine.suggest_titles("machne lerning", 3, None)[0], "Machine Learning Fundamentals");
+        assert_eq!(engine.suggest_titles("rust", 10, None).len(), 3);
+        assert!(engine.suggest_titles("   ", 3, None).is_empty());
+
+        engine
+            .add_document(Document { acl: vec!["finance".to_string()], ..make_doc("budget", "Secret Budget", "q3") })
+            .unwrap();
+        let eng = ["eng".to_string()];
+        assert_eq!(engine.suggest_titles("secret budget", 1, None), vec!["Secret Budget".to_string()]);
+        assert!(!engine.suggest_titles("secret budget", 10, Some(&eng)).contains(&"Secret Budget".to_string()));
+        assert_eq!(engine.suggest_titles("rust", 10, Some(&eng)).len(), 3);
+    }
+
+    #[test]
+    fn test_limit_caps_results_before_pagination() {
+        let engine = FerrumSearch::new();
+        for i in 0..6 {
+            engine.add_document(make_doc(&format!("rust{}", i), "Rust", "ownership")).unwrap();
+        }
+        add_fillers(&engine, 8);
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            limit: Some(3),
+            per_page: Some(10),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.results.len(), 3);
+        assert_eq!(results.total_hits, 3);
+        assert_eq!(results.total_pages, 1);
+    }
+
+    #[test]
+    fn test_silent_queries_skip_metrics_log_and_cache() {
+        let engine = FerrumSearch::new();
+        engine.set_query_cache(8);
+        engine.add_document(make_doc("1", "Rust", "ownership")).unwrap();
+
+        let silent = SearchQuery {
+            query: "rust".to_string(),
+            silent: true,
+            ..Default::default()
+        };
+        assert_eq!(engine.search(silent).unwrap().total_hits, 1);
+        assert_eq!(engine.query_metrics().query_count, 0);
+        assert!(engine.popular_queries("", 10).is_empty());
+        assert_eq!(engine.query_cache.read().unwrap().as_ref().unwrap().entries.len(), 0);
+
+        let normal = SearchQuery { query: "rust".to_string(), ..Default::default() };
+        engine.search(normal).unwrap();
+        assert_eq!(engine.query_metrics().query_count, 1);
+        assert_eq!(engine.popular_queries("", 10), vec!["rust".to_string()]);
+    }
+
+    #[test]
+    fn test_phonetic_matching_finds_sound_alikes() {
+        assert_eq!(PhoneticAlgorithm::Soundex.encode("Robert").as_deref(), Some("R163"));
+        assert_eq!(PhoneticAlgorithm::Soundex.encode("Ashcraft").as_deref(), Some("A261"));
+        assert_eq!(PhoneticAlgorithm::Metaphone.encode("Knight").as_deref(), Some("NT"));
+
+        for algorithm in [PhoneticAlgorithm::Soundex, PhoneticAlgorithm::Metaphone] {
+            let engine = FerrumSearch::new();
+            engine.add_document(make_doc("smith", "Profile", "John Smith, engineer")).unwrap();
+            engine.set_phonetic(Some(algorithm));
+            engine.add_document(make_doc("jones", "Profile", "Mary Jones, designer")).unwrap();
+            engine.add_document(make_doc("other", "Notes", "unrelated")).unwrap();
+
+            let query = |phonetic| SearchQuery {
+                query: "Smyth".to_string(),
+                phonetic,
+                ..Default::default()
+            };
+            assert_eq!(engine.search(query(false)).unwrap().total_hits, 0);
+            let results = engine.search(query(true)).unwrap();
+            let ids: Vec<_> = results.results.iter().map(|r| r.id.as_str()).collect();
+            assert_eq!(ids, vec!["smith"], "{:?}", algorithm);
+        }
+
+        let disabled = FerrumSearch::new();
+        let query = SearchQuery { query: "smyth".to_string(), phonetic: true, ..Default::default() };
+        assert!(matches!(disabled.search(query), Err(FerrumError::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn test_number_tokens_keep_measurements_whole() {
+        let analyzer = Analyzer::default().with_number_tokens();
+        assert_eq!(
+            analyzer.analyze("v2.0 ships at 3.5kg, in 1080p."),
+            vec!["v2.0", "ships", "3.5kg", "1080p"]
+        );
+
+        let engine = FerrumSearch::new();
+        engine.set_analyzer(Analyzer::default().with_number_tokens());
+        engine.add_document(make_doc("light", "Laptop", "weighs 3.5kg")).unwrap();
+        engine.add_document(make_doc("heavy", "Crate", "weighs 35kg")).unwrap();
+        engine.add_document(make_doc("screen", "Monitor", "streams 1080p video")).unwrap();
+        add_fillers(&engine, 3);
+
+        let ids = |text: &str| {
+            let results = engine.search(SearchQuery { query: text.to_string(), ..Default::default() }).unwrap();
+            results.results.into_iter().map(|r| r.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids("3.5kg"), vec!["light"]);
+        assert_eq!(ids("1080p"), vec!["screen"]);
+    }
+
+    #[test]
+    fn test_debug_scores_sum_to_total() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("both", "Rust", "async runtime internals")).unwrap();
+        engine.add_document(make_doc("one", "Python", "async frameworks")).unwrap();
+        add_fillers(&engine, 4);
+
+        let query = SearchQuery {
+            query: "rust async".to_string(),
+            debug_scores: true,
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let top = &results.results[0];
+        assert_eq!(top.id, "both");
+        let mut terms: Vec<_> = top.term_scores.keys().cloned().collect();
+        terms.sort();
+        assert_eq!(terms, vec!["async", "rust"]);
+        for result in &results.results {
+            let sum: f32 = result.term_scores.values().sum();
+            assert!((sum - result.score).abs() < 1e-5, "{} vs {}", sum, result.score);
+        }
+
+        let plain = engine.search(SearchQuery { query: "rust".to_string(), ..Default::default() }).unwrap();
+        assert!(plain.results[0].term_scores.is_empty());
+    }
+
+    #[test]
+    fn test_binary_index_round_trip_is_smaller_than_json() {
+        let engine = FerrumSearch::new();
+        engine.set_deterministic(true);
+        for i in 0..30 {
+            let mut doc = make_doc(
+                &Uuid::new_v4().to_string(),
+                &format!("Guide {}", i),
+                "rust ownership borrowing lifetimes and async programming patterns",
+            );
+            doc.numeric_metadata.insert("price".to_string(), i as f64);
+            doc.acl = vec!["staff".to_string()];
+            engine.add_document(doc).unwrap();
+        }
+        let mut special = make_doc("special", "Compilers", "parsing and rust codegen");
+        special.numeric_metadata.insert("price".to_string(), 50.0);
+        engine.add_document(special).unwrap();
+
+        let dir = std::env::temp_dir();
+        let json_path = dir.join(format!("ferrum-{}.json", Uuid::new_v4()));
+        let binary_path = dir.join(format!("ferrum-{}.bin", Uuid::new_v4()));
+        engine.save_to_file(&json_path, IndexFormat::Json).unwrap();
+        engine.save_to_file(&binary_path, IndexFormat::Binary).unwrap();
+        let json_size = std::fs::metadata(&json_path).unwrap().len();
+        let binary_size = std::fs::metadata(&binary_path).unwrap().len();
+        assert!(binary_size < json_size, "binary {} vs json {}", binary_size, json_size);
+
+        let query = || SearchQuery {
+            query: "rust codegen".to_string(),
+            numeric_filters: Some(HashMap::from([(
+                "price".to_string(),
+                NumericRange { min: Some(10.0), max: None },
+            )])),
+            ..Default::default()
+        };
+        let expected = engine.search(query()).unwrap();
+        for (path, format) in [(&json_path, IndexFormat::Json), (&binary_path, IndexFormat::Binary)] {
+            let restored = FerrumSearch::new();
+            restored.set_deterministic(true);
+            restored.load_from_file(path, format).unwrap();
+            let results = restored.search(query()).unwrap();
+            assert_eq!(results.total_hits, expected.total_hits);
+            let pairs = |r: &SearchResponse| r.results.iter().map(|x| (x.id.clone(), x.score)).collect::<Vec<_>>();
+            assert_eq!(pairs(&results), pairs(&expected));
+            assert_eq!(restored.autocomplete("own", 5), engine.autocomplete("own", 5));
+        }
+        std::fs::remove_file(json_path).unwrap();
+        std::fs::remove_file(binary_path).unwrap();
+    }
+
+    #[test]
+    fn test_interned_postings_stay_correct_and_compact() {
+        let engine = FerrumSearch::new();
+        let ids: Vec<String> = (0..200).map(|_| Uuid::new_v4().to_string()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            let topic = if i % 2 == 0 { "rust" } else { "python" };
+            engine.add_document(make_doc(id, "Guide", &format!("{} tutorial", topic))).unwrap();
+        }
+
+        // Freed handles are recycled without leaking into other postings
+        for id in &ids[..10] {
+            engine.remove_document(id).unwrap();
+        }
+        engine.add_document(make_doc("newcomer", "Notes", "python internals")).unwrap();
+        assert_eq!(engine.doc_handles.read().unwrap().ids.len(), 200);
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            per_page: Some(MAX_PER_PAGE),
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        let mut found: Vec<String> = results.results.into_iter().map(|r| r.id).collect();
+        found.sort();
+        let mut expected: Vec<String> = ids[10..].iter().step_by(2).cloned().collect();
+        expected.sort();
+        assert_eq!(found, expected);
+        assert!(posting_ids(&engine, "python").unwrap().contains(&"newcomer".to_string()));
+
+        // The live posting buffers against the same postings stored as id Strings
+        let index = engine.inverted_index.read().unwrap();
+        let doc_handles = engine.doc_handles.read().unwrap();
+        let handle_bytes: usize = index
+            .values()
+            .map(|postings| postings.capacity() * std::mem::size_of::<DocHandle>())
+            .sum();
+        let string_bytes: usize = index
+            .values()
+            .flatten()
+            .map(|&handle| std::mem::size_of::<String>() + doc_handles.id(handle).len())
+            .sum();
+        // 190 surviving guides with three terms each, plus the newcomer's three
+        assert_eq!(index.values().map(Vec::len).sum::<usize>(), 573);
+        assert!(handle_bytes * 10 < string_bytes, "{} vs {}", handle_bytes, string_bytes);
+    }
+
+    #[test]
+    fn test_autocomplete_breaks_frequency_ties_alphabetically() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Notes", "programs programming progress")).unwrap();
+        engine.add_document(make_doc("2", "Notes", "programs")).unwrap();
+
+        for _ in 0..5 {
+            assert_eq!(engine.autocomplete("prog", 3), vec!["programs", "programming", "progress"]);
+        }
+        assert_eq!(engine.autocomplete("prog", 2), vec!["programs", "programming"]);
+    }
+
+    #[test]
+    fn test_response_stamps_index_generation() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "ownership")).unwrap();
+        let query = || SearchQuery { query: "rust".to_string(), ..Default::default() };
+
+        let before = engine.search(query()).unwrap();
+        assert_eq!(before.index_generation, engine.index_generation());
+
+        engine.add_document(make_doc("2", "Go", "goroutines")).unwrap();
+        assert!(engine.index_generation() > before.index_generation);
+        let after = engine.search(query()).unwrap();
+        assert_eq!(after.index_generation, engine.index_generation());
+    }
+
+    #[test]
+    fn test_has_match_short_circuits_without_results() {
+        let engine = FerrumSearch::new();
+        let mut doc = make_doc("1", "Rust", "ownership and borrowing");
+        doc.metadata.insert("category".to_string(), "programming".to_string());
+        engine.add_document(doc).unwrap();
+        engine.add_document(make_doc("2", "Go", "goroutines and channels")).unwrap();
+
+        let query = |text: &str| SearchQuery { query: text.to_string(), ..Default::default() };
+        assert!(engine.has_match(query("borrowing")).unwrap());
+        assert!(!engine.has_match(query("haskell")).unwrap());
+        assert!(!engine.has_match(query("borrowing -ownership")).unwrap());
+        let phrase = |text: &str| SearchQuery { query_type: QueryType::Phrase, ..query(text) };
+        assert!(engine.has_match(phrase("and borrowing")).unwrap());
+        assert!(!engine.has_match(phrase("borrowing ownership")).unwrap());
+        assert!(engine.has_match(SearchQuery { operator: Some(QueryOperator::And), ..query("rust ownership") }).unwrap());
+        assert!(!engine.has_match(SearchQuery { operator: Some(QueryOperator::And), ..query("rust goroutines") }).unwrap());
+
+        let filtered = |category: &str| SearchQuery {
+            filters: Some(HashMap::from([("category".to_string(), category.to_string())])),
+            ..query("rust goroutines")
+        };
+        assert!(engine.has_match(filtered("programming")).unwrap());
+        assert!(!engine.has_match(filtered("cooking")).unwrap());
+
+        // Existence checks never go through `search`
+        assert_eq!(engine.query_metrics().query_count, 0);
+        assert!(engine.popular_queries("", 10).is_empty());
+    }
+
+    #[test]
+    fn test_per_field_highlight_limits() {
+        let engine = FerrumSearch::new();
+        let gap = vec!["lorem"; 20].join(" ");
+        let content = format!("rust one {gap} rust two {gap} rust three");
+        engine.add_document(make_doc("doc", "Rust and more Rust", &content)).unwrap();
+        engine.add_document(make_doc("other", "Other", "unrelated")).unwrap();
+        engine.add_document(make_doc("more", "More", "nothing")).unwrap();
+
+        let query = |config: HighlightConfig| SearchQuery {
+            query: "rust".to_string(),
+            highlight: Some(true),
+            highlight_config: config,
+            ..Default::default()
+        };
+        let config = HighlightConfig {
+            max_snippets: 3,
+            field_max_snippets: HashMap::from([("title".to_string(), 1), ("content".to_string(), 2)]),
+        };
+        let results = engine.search(query(config)).unwrap();
+        let fields = &results.results[0].field_highlights;
+        assert_eq!(fields["title"].len(), 1);
+        assert_eq!(fields["content"].len(), 2);
+        assert!(fields["content"][1].contains("two"));
+
+        // Title and content are only reported when budgeted
+        let results = engine.search(query(HighlightConfig::default())).unwrap();
+        assert!(results.results[0].field_highlights.is_empty());
+
+        // Unlisted metadata fields fall back to the global budget
+        engine.set_searchable_metadata(vec!["summary".to_string()]);
+        let mut doc = make_doc("doc", "Rust and more Rust", &content);
+        doc.metadata.insert("summary".to_string(), content.clone());
+        engine.add_document(doc).unwrap();
+        let config = HighlightConfig { max_snippets: 2, ..Default::default() };
+        let results = engine.search(query(config)).unwrap();
+        let fields = &results.results[0].field_highlights;
+        assert_eq!(fields["summary"].len(), 2);
+        assert!(!fields.contains_key("content"));
+    }
+
+    #[test]
+    fn test_filter_only_query_browses_without_scoring() {
+        let engine = FerrumSearch::new();
+        engine.set_deterministic(true);
+        for (id, category, price) in [("c", "books", 5.0), ("a", "music", 9.0), ("b", "books", 1.0), ("d", "books", 7.0)] {
+            let mut doc = make_doc(id, "Item", "catalog entry");
+            doc.metadata.insert("category".to_string(), category.to_string());
+            doc.numeric_metadata.insert("price".to_string(), price);
+            engine.add_document(doc).unwrap();
+        }
+
+        let browse = |text: &str, sort_by: Option<&str>| SearchQuery {
+            query: text.to_string(),
+            filters: Some(HashMap::from([("category".to_string(), "books".to_string())])),
+            sort_by: sort_by.map(String::from),
+            ..Default::default()
+        };
+        let ids = |query| engine.search(query).unwrap().results.into_iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids(browse("", None)), vec!["c", "b", "d"]);
+        assert_eq!(ids(browse("a", Some("-price"))), vec!["d", "c", "b"]);
+        assert!(engine.search(browse("", None)).unwrap().results.iter().all(|r| r.score == 0.0));
+
+        let unfiltered = SearchQuery { query: "".to_string(), ..Default::default() };
+        assert_eq!(engine.search(unfiltered).unwrap().total_hits, 0);
+    }
+
+    #[test]
+    fn test_page_past_the_end_is_flagged_out_of_range() {
+        let engine = FerrumSearch::new();
+        for i in 0..4 {
+            engine.add_document(make_doc(&format!("rust{}", i), "Rust", "ownership")).unwrap();
+        }
+        add_fillers(&engine, 6);
+
+        let page = |n| SearchQuery {
+            query: "rust".to_string(),
+            page: Some(n),
+            per_page: Some(2),
+            ..Default::default()
+        };
+        let last = engine.search(page(2)).unwrap();
+        assert!(!last.out_of_range);
+        assert_eq!(last.results.len(), 2);
+
+        let beyond = engine.search(page(99)).unwrap();
+        assert!(beyond.out_of_range);
+        assert!(beyond.results.is_empty());
+        assert_eq!((beyond.page, beyond.total_pages, beyond.total_hits), (99, 2, 4));
+        assert_eq!(beyond.prev_page, Some(2));
+        assert_eq!(beyond.next_page, None);
+
+        let none = SearchQuery { query: "haskell".to_string(), ..Default::default() };
+        assert!(!engine.search(none).unwrap().out_of_range);
+    }
+
+    #[test]
+    fn test_full_match_bonus_rewards_complete_coverage() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("full", "Notes", "rust async plus some other words here")).unwrap();
+        engine.add_document(make_doc("partial", "Rust", "rust rust rust")).unwrap();
+        for i in 0..4 {
+            engine.add_document(make_doc(&format!("filler{}", i), "Other", "async unrelated")).unwrap();
+        }
+
+        let ids = || {
+            let query = SearchQuery { query: "rust async".to_string(), ..Default::default() };
+            engine.search(query).unwrap().results.into_iter().map(|r| r.id).take(2).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(), vec!["partial", "full"]);
+
+        engine.set_full_match_bonus(Some(1.0));
+        assert_eq!(ids(), vec!["full", "partial"]);
+    }
+
+    #[test]
+    fn test_export_analytics_records_hits_and_latency() {
+        let engine = FerrumSearch::new();
+        engine.set_deterministic(true);
+        engine.add_document(make_doc("1", "Rust", "ownership")).unwrap();
+        engine.add_document(make_doc("2", "Rust Async", "runtimes")).unwrap();
+        engine.add_document(make_doc("3", "Go", "goroutines")).unwrap();
+
+        for text in ["rust", "Rust  async", "haskell"] {
+            engine.search(SearchQuery { query: text.to_string(), ..Default::default() }).unwrap();
+        }
+
+        let recent = engine.recent_queries(2);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].query, "haskell");
+        assert_eq!(recent[1].query, "rust async");
+
+        let mut out = Vec::new();
+        engine.export_analytics(&mut out).unwrap();
+        let records: Vec<QueryRecord> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let summary: Vec<(&str, usize, usize, u64)> = records
+            .iter()
+            .map(|r| (r.query.as_str(), r.term_count, r.hit_count, r.latency_ms))
+            .collect();
+        assert_eq!(summary, vec![("rust", 1, 2, 0), ("rust async", 2, 2, 0), ("haskell", 1, 0, 0)]);
+        // Logging reads the logical clock without advancing it
+        assert!(records.iter().all(|record| record.timestamp == 3));
+    }
+
+    #[test]
+    fn test_idf_matches_search_scoring() {
+        let engine = FerrumSearch::new();
+        engine.set_tf_saturation(TfSaturation::LogTf);
+        engine.add_document(make_doc("1", "Rust", "ownership")).unwrap();
+        engine.add_document(make_doc("2", "Rust", "borrowing")).unwrap();
+        add_fillers(&engine, 5);
+
+        let expected = ((7.0f32 - 2.0 + 0.5) / (2.0 + 0.5)).ln();
+        assert!((engine.idf("rust") - expected).abs() < 1e-6);
+        assert_eq!(engine.idf("haskell"), 0.0);
+
+        // With log-TF saturation a single occurrence contributes idf * ln(2)
+        let query = SearchQuery { query: "rust".to_string(), debug_scores: true, ..Default::default() };
+        let results = engine.search(query).unwrap();
+        let used = results.results[0].term_scores["rust"] / 2f32.ln();
+        assert!((engine.idf("rust") - used).abs() < 1e-5, "{} vs {}", engine.idf("rust"), used);
+    }
+
+    #[test]
+    fn test_exact_title_match_ranks_first() {
+        let engine = FerrumSearch::new();
+        for doc in demo_documents() {
+            engine.add_document(doc).unwrap();
+        }
+        engine
+            .add_document(make_doc("dense", "Understanding Search Algorithms Notes", "understanding search algorithms"))
+            .unwrap();
+        add_fillers(&engine, 6);
+
+        let top = |text: &str| {
+            let query = SearchQuery { query: text.to_string(), ..Default::default() };
+            engine.search(query).unwrap().results[0].id.clone()
+        };
+        assert_eq!(top("understanding search algorithms"), "dense");
+
+        engine.set_exact_title_boost(Some(100.0));
+        let title = demo_documents().into_iter().find(|d| d.title == "Understanding Search Algorithms").unwrap();
+        assert_eq!(top(&title.title.to_uppercase()), title.id);
+        // Operators and quotes are not part of the compared text
+        assert_eq!(top("+understanding search algorithms -legacy"), title.id);
+        assert_eq!(top("\"understanding search algorithms\""), title.id);
+    }
+
+    #[test]
+    fn test_shingles_match_adjacent_words() {
+        let analyzer = Analyzer::default().with_shingles(3);
+        assert_eq!(
+            analyzer.analyze("Modern web development"),
+            vec!["modern", "web", "development", "modern web", "web development", "modern web development"]
+        );
+
+        let engine = FerrumSearch::new();
+        engine.set_analyzer(Analyzer::default().with_shingles(2));
+        engine.add_document(make_doc("adjacent", "Guide", "web development tools")).unwrap();
+        engine.add_document(make_doc("apart", "Guide", "development tools web")).unwrap();
+        add_fillers(&engine, 4);
+        assert_eq!(posting_ids(&engine, "web development"), Some(vec!["adjacent".to_string()]));
+
+        let query = SearchQuery {
+            query: "web development".to_string(),
+            debug_scores: true,
+            ..Default::default()
+        };
+        let results = engine.search(query).unwrap();
+        assert_eq!(results.total_hits, 2);
+        assert_eq!(results.results[0].id, "adjacent");
+        assert!(results.results[0].term_scores["web development"] > 0.0);
+    }
+
+    #[test]
+    fn test_load_stop_words_from_file() {
+        let path = std::env::temp_dir().join(format!("ferrum-stopwords-{}.txt", Uuid::new_v4()));
+        std::fs::write(&path, "plaintiff\nDefendant\n\n  hereby \nplaintiff\n").unwrap();
+
+        let engine = FerrumSearch::new();
+        assert_eq!(engine.load_stop_words_from_file(&path).unwrap(), 3);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(engine.tokenize("The plaintiff hereby sued the defendant company"), vec!["the", "sued", "the", "company"]);
+
+        engine.add_document(make_doc("1", "Filing", "plaintiff motion")).unwrap();
+        assert_eq!(posting_ids(&engine, "plaintiff"), None);
+        assert!(engine.load_stop_words_from_file(Path::new("/nonexistent/stopwords.txt")).is_err());
+    }
+
+    #[test]
+    fn test_fallback_relaxes_strict_query() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("both", "Rust", "memory safety in rust")).unwrap();
+        engine.add_document(make_doc("rust-only", "Rust", "fearless concurrency")).unwrap();
+        engine.add_document(make_doc("typo", "Notes", "memmory management")).unwrap();
+        add_fillers(&engine, 4);
+
+        let strict = SearchQuery {
+            query: "rust memory".to_string(),
+            operator: Some(QueryOperator::And),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(strict.clone()).unwrap().total_hits, 1);
+
+        let relaxed = engine
+            .search(SearchQuery {
+                fallback: Some(FallbackStrategy { min_results: 3, fuzzy: true }),
+                ..strict
+            })
+            .unwrap();
+        let flagged: Vec<(&str, bool)> = relaxed.results.iter().map(|r| (r.id.as_str(), r.fallback)).collect();
+        assert_eq!(flagged.len(), 3);
+        assert_eq!(flagged[0], ("both", false));
+        assert!(flagged[1..].contains(&("rust-only", true)));
+        assert!(flagged[1..].contains(&("typo", true)));
+    }
+
+    #[test]
+    fn test_id_policy_rejects_malformed_ids() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("line\nbreak", "Before", "accepted without a policy")).unwrap();
+
+        engine.set_id_policy(Some(IdPolicy::default()));
+        let err = engine.add_document(make_doc("bad\nid", "Title", "content")).unwrap_err();
+        assert!(matches!(err, FerrumError::InvalidId { ref id, .. } if id == "bad\nid"));
+        assert!(engine.insert_document(make_doc(&"x".repeat(257), "Title", "content")).is_err());
+        engine.add_document(make_doc("docs/guide-1", "Title", "content")).unwrap();
+
+        engine.set_id_policy(Some(IdPolicy {
+            allowed_symbols: Some("-_".to_string()),
+            ..Default::default()
+        }));
+        assert!(engine.add_document(make_doc("docs/guide-2", "Title", "content")).is_err());
+        engine.add_document(make_doc("guide_2", "Title", "content")).unwrap();
+        assert_eq!(engine.snapshot_documents().len(), 3);
+    }
+
+    #[test]
+    fn test_title_search_uses_title_index() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("titled", "Rust Guide", "Ownership and borrowing")).unwrap();
+        engine.add_document(make_doc("body", "Language Notes", "Rust has traits and guide pages")).unwrap();
+        add_fillers(&engine, 4);
+        engine.add_document(make_doc("renamed", "Rust Basics", "intro")).unwrap();
+        engine.add_document(make_doc("renamed", "Cooking Basics", "rust removal from pans")).unwrap();
+
+        let title_query = |text: &str| SearchQuery {
+            query: text.to_string(),
+            search_fields: Some(vec!["title".to_string()]),
+            ..Default::default()
+        };
+        let ids = |query: SearchQuery| -> Vec<String> {
+            engine.search(query).unwrap().results.into_iter().map(|r| r.id).collect()
+        };
+        assert_eq!(ids(title_query("rust")), vec!["titled"]);
+        assert!(ids(title_query("borrowing")).is_empty());
+
+        // Candidates come from title postings, not the merged index
+        assert_eq!(engine.estimate_cost(&title_query("rust guide")).candidate_docs, 1);
+        assert_eq!(engine.estimate_cost(&SearchQuery { search_fields: None, ..title_query("rust guide") }).candidate_docs, 3);
+        assert_eq!(engine.title_index.read().unwrap()["basics"].len(), 1);
+        assert!(!engine.title_index.read().unwrap().contains_key("borrowing"));
+
+        engine.remove_document("titled").unwrap();
+        assert!(ids(title_query("rust")).is_empty());
+        assert!(!engine.title_index.read().unwrap().contains_key("rust"));
+    }
+
+    #[test]
+    fn test_co_occurring_terms() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "memory safety without garbage")).unwrap();
+        engine.add_document(make_doc("2", "Rust", "memory ownership")).unwrap();
+        engine.add_document(make_doc("3", "Rust", "fearless concurrency")).unwrap();
+        engine.add_document(make_doc("4", "Python", "memory garbage collector")).unwrap();
+
+        assert_eq!(
+            engine.co_occurring_terms("rust", 3),
+            vec![("memory".to_string(), 2), ("concurrency".to_string(), 1), ("fearless".to_string(), 1)]
+        );
+        assert_eq!(engine.co_occurring_terms("rust", 10).len(), 7);
+        assert!(engine.co_occurring_terms("missing", 5).is_empty());
+    }
+
+    #[test]
+    fn test_max_content_bytes_policy() {
+        let engine = FerrumSearch::new();
+        let blob = format!("héllo {}", "padding ".repeat(100));
+        engine.set_max_content_bytes(Some(64), OversizedContentPolicy::Reject);
+        let err = engine.add_document(make_doc("blob", "Dump", &blob)).unwrap_err();
+        assert_eq!(err, FerrumError::ContentTooLarge { id: "blob".to_string(), bytes: blob.len(), limit: 64 });
+        assert!(engine.snapshot_documents().is_empty());
+        engine.add_document(make_doc("small", "Note", "fits easily")).unwrap();
+
+        engine.set_max_content_bytes(Some(2), OversizedContentPolicy::Truncate);
+        engine.add_document(make_doc("blob", "Dump", &blob)).unwrap();
+        let stored = engine.snapshot_documents().into_iter().find(|d| d.id == "blob").unwrap();
+        assert_eq!(stored.content, "h");
+        assert_eq!(stored.metadata[ORIGINAL_CONTENT_BYTES_KEY], blob.len().to_string());
+        assert!(posting_ids(&engine, "padding").is_none());
+    }
+
+    #[test]
+    fn test_group_results_by_matched_field() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("in-title", "Rust Guide", "Ownership and borrowing")).unwrap();
+        engine.add_document(make_doc("in-content", "Language Notes", "Rust has traits")).unwrap();
+        add_fillers(&engine, 4);
+
+        let query = SearchQuery {
+            query: "rust".to_string(),
+            group_by_field: true,
+            ..Default::default()
+        };
+        let response = engine.search(query.clone()).unwrap();
+        let ids = |field: &str| -> Vec<&str> {
+            response.field_groups[field].iter().map(|r| r.id.as_str()).collect()
+        };
+        assert_eq!(response.field_groups.len(), 2);
+        assert_eq!(ids("title"), vec!["in-title"]);
+        assert_eq!(ids("content"), vec!["in-content"]);
+        assert_eq!(response.results.len(), 2);
+
+        let flat = engine.search(SearchQuery { group_by_field: false, ..query }).unwrap();
+        assert!(flat.field_groups.is_empty());
+    }
+
+    #[test]
+    fn test_nan_scores_sort_last() {
+        let engine = FerrumSearch::new();
+        engine.set_deterministic(true);
+        for id in ["a", "b", "c", "d"] {
+            engine.add_document(make_doc(id, "Rust", "systems language")).unwrap();
+        }
+        add_fillers(&engine, 6);
+        engine.word_frequencies.write().unwrap().get_mut("b").unwrap().insert("rust".to_string(), f32::NAN);
+
+        for _ in 0..5 {
+            let query = SearchQuery {
+                query: "rust".to_string(),
+                include_score_stats: true,
+                ..Default::default()
+            };
+            let response = engine.search(query).unwrap();
+            let ids: Vec<&str> = response.results.iter().map(|r| r.id.as_str()).collect();
+            assert_eq!(ids, vec!["a", "c", "d", "b"]);
+            assert_eq!(response.results[3].score, 0.0);
+            assert!(response.score_stats.unwrap().mean.is_finite());
+
+            let json = serde_json::to_string(&response).unwrap();
+            let parsed: SearchResponse = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed.results[3].score, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_recompute_document_lengths_repairs_scoring() {
+        let engine = FerrumSearch::new();
+        for doc in demo_documents() {
+            engine.add_document(doc).unwrap();
+        }
+        engine.add_document(make_doc("empty", "", "")).unwrap();
+        let query = || SearchQuery { query: "rust memory".to_string(), ..Default::default() };
+        let scores = |response: SearchResponse| -> Vec<(String, f32)> {
+            response.results.into_iter().map(|r| (r.id, r.score)).collect()
+        };
+        let expected = scores(engine.search(query()).unwrap());
+        let lengths = engine.document_lengths.read().unwrap().clone();
+
+        engine.document_lengths.write().unwrap().insert("rust-guide".to_string(), 10_000);
+        engine.recompute_avg_doc_len();
+        assert_ne!(scores(engine.search(query()).unwrap()), expected);
+
+        engine.recompute_document_lengths();
+        assert_eq!(*engine.document_lengths.read().unwrap(), lengths);
+        assert_eq!(scores(engine.search(query()).unwrap()), expected);
+    }
+
+    #[test]
+    fn test_sharded_search_matches_single_shard() {
+        let engine = FerrumSearch::new();
+        engine.set_deterministic(true);
+        for doc in demo_documents() {
+            engine.add_document(doc).unwrap();
+        }
+        for i in 0..40 {
+            let content = format!("rust search note {} with programming and web {}", i, "memory ".repeat(i % 5));
+            engine.add_document(make_doc(&format!("note{}", i), "Note", &content)).unwrap();
+        }
+
+        let queries = [
+            SearchQuery { query: "rust programming".to_string(), per_page: Some(100), ..Default::default() },
+            SearchQuery { query: "memory web".to_string(), operator: Some(QueryOperator::And), per_page: Some(100), ..Default::default() },
+            SearchQuery { query: "serch".to_string(), fuzzy: true, per_page: Some(100), ..Default::default() },
+        ];
+        let ranked = |query: &SearchQuery| -> Vec<(String, f32, Vec<String>)> {
+            let response = engine.search(query.clone()).unwrap();
+            assert!(response.total_hits > 0);
+            response.results.into_iter().map(|r| (r.id, r.score, r.matched_terms)).collect()
+        };
+
+        let single: Vec<_> = queries.iter().map(ranked).collect();
+        engine.set_search_shards(4);
+        engine.set_min_postings_per_shard(8);
+        let sharded: Vec<_> = queries.iter().map(ranked).collect();
+        assert_eq!(sharded, single);
+    }
+
+    #[test]
+    fn test_cancelled_search_returns_flag() {
+        /// Flips the token when the query text is analyzed, i.e. partway
+        /// through a search, before scoring starts.
+        struct CancelOn(CancellationToken);
+
+        impl TokenFilter for CancelOn {
+            fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+                if tokens.iter().any(|t| t == "abandon") {
+                    self.0.store(true, atomic::Ordering::Relaxed);
+                }
+                tokens
+            }
+        }
+
+        let token = CancellationToken::default();
+        let engine = FerrumSearch::new();
+        engine.set_analyzer(Analyzer::default().with_filter(CancelOn(token.clone())));
+        engine.set_query_cache(10);
+        for doc in demo_documents() {
+            engine.add_document(doc).unwrap();
+        }
+        token.store(false, atomic::Ordering::Relaxed);
+
+        let query = |text: &str| SearchQuery {
+            query: text.to_string(),
+            fuzzy: true,
+            cancellation: Some(token.clone()),
+            ..Default::default()
+        };
+        let response = engine.search(query("rust abandon")).unwrap();
+        assert!(response.cancelled);
+        assert!(response.results.is_empty());
+
+        token.store(false, atomic::Ordering::Relaxed);
+        let response = engine.search(query("rust")).unwrap();
+        assert!(!response.cancelled);
+        assert!(response.total_hits > 0);
     }
 
-    // Filtered search
-    let filtered_query = SearchQuery {
-        query: "development".to_string(),
-        filters: Some({
-            let mut filters = HashMap::new();
-            filters.insert("category".to_string(), "web".to_string());
-            filters
-        }),
-        ..Default::default()
-    };
-    
-    match engine.search(filtered_query) {
-        Ok(response) => {
-            println!("\n📊 Filtered Query: 'development' + category:web ({}ms)", response.query_time_ms);
-            println!("   Results: {}/{}", response.results.len(), response.total_hits);
-            for result in &response.results {
-                println!("   • {} (score: {:.2})", result.title, result.score);
+    #[test]
+    fn test_result_content_len_respects_char_boundaries() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("cafe", "Café", "héllo wörld")).unwrap();
+
+        let query = |len: usize| SearchQuery {
+            query: "café".to_string(),
+            result_content_len: Some(len),
+            ..Default::default()
+        };
+        assert_eq!(engine.search(query(2)).unwrap().results[0].content, "hé...");
+        assert_eq!(engine.search(query(7)).unwrap().results[0].content, "héllo w...");
+        assert_eq!(engine.search(query(8)).unwrap().results[0].content, "héllo wö...");
+        // 11 characters, but 13 bytes
+        assert_eq!(engine.search(query(11)).unwrap().results[0].content, "héllo wörld");
+    }
+
+    #[test]
+    fn test_shingles_keep_phrases_and_lengths_intact() {
+        let plain = FerrumSearch::new();
+        let shingled = FerrumSearch::new();
+        shingled.set_analyzer(Analyzer::default().with_shingles(3));
+        for engine in [&plain, &shingled] {
+            engine.add_document(make_doc("adjacent", "Guide", "modern web development tools")).unwrap();
+            engine.add_document(make_doc("apart", "Guide", "development tools for the web")).unwrap();
+        }
+        assert_eq!(*shingled.document_lengths.read().unwrap(), *plain.document_lengths.read().unwrap());
+
+        let phrase = |engine: &FerrumSearch, text: &str| -> Vec<String> {
+            let query = SearchQuery {
+                query: text.to_string(),
+                query_type: QueryType::Phrase,
+                ..Default::default()
+            };
+            engine.search(query).unwrap().results.into_iter().map(|r| r.id).collect()
+        };
+        assert_eq!(phrase(&shingled, "web development"), vec!["adjacent"]);
+        assert_eq!(phrase(&shingled, "modern web development"), vec!["adjacent"]);
+        assert_eq!(phrase(&shingled, "development tools"), phrase(&plain, "development tools"));
+
+        // Under AND, the shingle isn't one more required term
+        let and_query = SearchQuery {
+            query: "tools web".to_string(),
+            operator: Some(QueryOperator::And),
+            ..Default::default()
+        };
+        assert_eq!(shingled.search(and_query).unwrap().total_hits, 2);
+    }
+
+    #[test]
+    fn test_has_match_honors_browse_mode() {
+        let engine = FerrumSearch::new();
+        for doc in demo_documents() {
+            engine.add_document(doc).unwrap();
+        }
+        let browse = |filters: &[(&str, &str)], text: &str| SearchQuery {
+            query: text.to_string(),
+            filters: Some(filters.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+            ..Default::default()
+        };
+
+        for query in [
+            browse(&[("category", "algorithms")], ""),
+            browse(&[("category", "algorithms")], "-rust"),
+            browse(&[("category", "algorithms")], "-search"),
+            browse(&[("category", "missing")], ""),
+        ] {
+            let searched = engine.search(query.clone()).unwrap().total_hits > 0;
+            assert_eq!(engine.has_match(query.clone()).unwrap(), searched, "{:?}", query.query);
+        }
+        assert!(engine.has_match(browse(&[("category", "algorithms")], "")).unwrap());
+        assert!(!engine.has_match(browse(&[("category", "algorithms")], "-search")).unwrap());
+        assert!(!engine.has_match(SearchQuery::default()).unwrap());
+    }
+
+    #[test]
+    fn test_fallback_reaches_has_match_and_cancellation() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("rust-only", "Rust", "fearless concurrency")).unwrap();
+        engine.add_document(make_doc("typo", "Notes", "memmory management")).unwrap();
+        add_fillers(&engine, 4);
+
+        let strict = SearchQuery {
+            query: "rust memory".to_string(),
+            operator: Some(QueryOperator::And),
+            ..Default::default()
+        };
+        assert!(!engine.has_match(strict.clone()).unwrap());
+        let relaxed = SearchQuery {
+            fallback: Some(FallbackStrategy { min_results: 1, fuzzy: false }),
+            ..strict.clone()
+        };
+        assert!(engine.search(relaxed.clone()).unwrap().total_hits > 0);
+        assert!(engine.has_match(relaxed).unwrap());
+
+        // Cancelled while analyzing the query a second time, i.e. in the
+        // relaxed pass after the strict one finished
+        struct CancelOnSecondPass(CancellationToken, std::sync::atomic::AtomicUsize);
+
+        impl TokenFilter for CancelOnSecondPass {
+            fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+                if tokens == ["rust", "memory"] && self.1.fetch_add(1, atomic::Ordering::Relaxed) == 1 {
+                    self.0.store(true, atomic::Ordering::Relaxed);
+                }
+                tokens
             }
-        },
-        Err(e) => println!("❌ Filtered search failed: {}", e),
+        }
+        let token = CancellationToken::default();
+        let filter = CancelOnSecondPass(token.clone(), Default::default());
+        engine.set_analyzer(Analyzer::default().with_filter(filter));
+        let response = engine
+            .search(SearchQuery {
+                fallback: Some(FallbackStrategy { min_results: 5, fuzzy: true }),
+                cancellation: Some(token.clone()),
+                ..strict
+            })
+            .unwrap();
+        assert!(token.load(atomic::Ordering::Relaxed));
+        assert!(response.cancelled);
+        assert!(response.results.is_empty());
     }
 
-    // Autocomplete demo
-    println!("\n🔤 Autocomplete for 'prog':");
-    let suggestions = engine.autocomplete("prog", 5);
-    for suggestion in suggestions {
-        println!("   • {}", suggestion);
+    #[test]
+    fn test_change_stream_covers_every_mutator() {
+        use std::sync::Mutex;
+
+        let primary = FerrumSearch::new();
+        let replica = FerrumSearch::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        primary.set_change_listener(Box::new(move |event| recorded.lock().unwrap().push(event)));
+        let changes = primary.subscribe_changes();
+
+        let rebuilt = FerrumSearch::new();
+        let mut doc = make_doc("a", "Rust", "Systems programming");
+        doc.metadata.insert("kind".to_string(), "guide".to_string());
+        rebuilt.add_document(doc).unwrap();
+        rebuilt.add_document(make_doc("b", "Web", "Frameworks")).unwrap();
+
+        primary.add_document(make_doc("old", "Old", "Stale")).unwrap();
+        primary.replace_with(rebuilt);
+        primary.rename_metadata_key("kind", "category").unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                ChangeEvent::Added("old".to_string()),
+                ChangeEvent::Replaced,
+                ChangeEvent::Added("a".to_string()),
+            ]
+        );
+
+        for event in changes.try_iter() {
+            replica.apply_change(event).unwrap();
+        }
+        let mut ids: Vec<String> = replica.snapshot_documents().into_iter().map(|doc| doc.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "b"]);
+        let a = replica.snapshot_documents().into_iter().find(|doc| doc.id == "a").unwrap();
+        assert_eq!(a.metadata.get("category").map(String::as_str), Some("guide"));
+        assert!(!a.metadata.contains_key("kind"));
     }
 
-    // Stats
-    let stats = engine.get_stats();
-    println!("\n📈 Index Statistics:");
-    println!("   Documents: {}", stats.total_documents);
-    println!("   Index Size: {:.2} MB", stats.index_size_mb);
-    println!("   Version: {}", stats.version);
-    
-    println!("\n🚀 FerrumSearch is ready for production!");
-}
\ No newline at end of file
+    #[test]
+    fn test_poisoned_swap_lock_surfaces_as_error() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Rust", "Systems")).unwrap();
+        std::thread::scope(|s| {
+            let poisoner = s.spawn(|| {
+                let _guard = engine.swap_lock.write().unwrap();
+                panic!("poison the swap lock");
+            });
+            assert!(poisoner.join().is_err());
+        });
+
+        let query = SearchQuery { query: "rust".to_string(), ..Default::default() };
+        assert_eq!(engine.search(query).unwrap_err(), FerrumError::LockPoisoned);
+        assert_eq!(engine.add_document(make_doc("2", "Go", "Concurrency")).unwrap_err(), FerrumError::LockPoisoned);
+        assert_eq!(engine.remove_document("1").unwrap_err(), FerrumError::LockPoisoned);
+        assert_eq!(engine.rename_metadata_key("a", "b").unwrap_err(), FerrumError::LockPoisoned);
+        assert_eq!(engine.clear_index().unwrap_err(), FerrumError::LockPoisoned);
+    }
+
+    #[test]
+    fn test_cancellation_stops_term_expansion() {
+        let engine = FerrumSearch::new();
+        for i in 0..1000 {
+            engine.add_document(make_doc(&format!("doc{}", i), "Note", &format!("term{}", i))).unwrap();
+        }
+        let token = CancellationToken::default();
+        let index = engine.inverted_index.read().unwrap();
+
+        assert!(engine.fuzzy_expand_token("term1", &index, Some(&token)).0.len() > 1);
+        assert!(!engine.prefix_expand_token("term", &index, Some(&token)).0.is_empty());
+
+        // Only the exact term survives a cancelled vocabulary scan
+        token.store(true, atomic::Ordering::Relaxed);
+        assert_eq!(engine.fuzzy_expand_token("term1", &index, Some(&token)).0, vec!["term1"]);
+        assert!(engine.prefix_expand_token("term", &index, Some(&token)).0.is_empty());
+        let query = SearchQuery { fuzzy: true, cancellation: Some(token.clone()), ..Default::default() };
+        let (groups, _) = engine.expand_query_terms(&["term1".to_string(), "term2".to_string()], &query, &index);
+        assert!(groups.iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn test_cancellation_after_scoring_starts() {
+        /// Flips the token when a lone query word is re-analyzed for exact
+        /// matching, after the pre-scoring cancellation check has passed.
+        struct CancelOnSurface(CancellationToken);
+
+        impl TokenFilter for CancelOnSurface {
+            fn filter(&self, tokens: Vec<String>) -> Vec<String> {
+                if tokens == ["abandon"] {
+                    self.0.store(true, atomic::Ordering::Relaxed);
+                }
+                tokens
+            }
+        }
+
+        let token = CancellationToken::default();
+        let engine = FerrumSearch::new();
+        engine.set_analyzer(Analyzer::default().with_filter(CancelOnSurface(token.clone())));
+        engine.set_exact_match_boost(2.0);
+        for i in 0..2 * CANCELLATION_CHECK_INTERVAL {
+            engine.add_document(make_doc(&format!("doc{}", i), "Note", "rust")).unwrap();
+        }
+
+        let query = |text: &str| SearchQuery {
+            query: text.to_string(),
+            cancellation: Some(token.clone()),
+            ..Default::default()
+        };
+        let response = engine.search(query("rust abandon")).unwrap();
+        assert!(token.load(atomic::Ordering::Relaxed));
+        assert!(response.cancelled);
+        assert!(response.results.is_empty());
+
+        token.store(false, atomic::Ordering::Relaxed);
+        let response = engine.search(query("rust")).unwrap();
+        assert!(!response.cancelled);
+        assert_eq!(response.total_hits, 2 * CANCELLATION_CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn test_title_only_search_excludes_by_any_field() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("plain", "Rust Guide", "Ownership")).unwrap();
+        engine.add_document(make_doc("unsafe", "Rust Tricks", "Unsafe pointers")).unwrap();
+        add_fillers(&engine, 4);
+        let query = SearchQuery {
+            query: "rust -unsafe".to_string(),
+            search_fields: Some(vec!["title".to_string()]),
+            ..Default::default()
+        };
+        let ids: Vec<String> = engine.search(query).unwrap().results.into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["plain"]);
+
+        // Retitling drops only the old title's postings
+        engine.add_document(make_doc("plain", "Zig Guide", "Ownership")).unwrap();
+        let title_index = engine.title_index.read().unwrap();
+        assert_eq!(title_index["rust"].len(), 1);
+        assert_eq!(title_index["guide"].len(), 1);
+        assert_eq!(title_index["zig"].len(), 1);
+    }
+
+    #[test]
+    fn test_repeated_terms_post_once_per_document() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("1", "Notes", "rust rust rust")).unwrap();
+        engine.add_document(make_doc("2", "Notes", "rust once")).unwrap();
+        add_fillers(&engine, 4);
+        engine.append_content("2", "rust again rust").unwrap();
+
+        assert_eq!(posting_ids(&engine, "rust").unwrap().len(), 2);
+        assert_eq!(engine.idf("rust"), FerrumSearch::idf_for(6, 2));
+    }
+
+    #[test]
+    fn test_append_content_matches_fresh_indexing() {
+        let appended = FerrumSearch::new();
+        appended.add_document(make_doc("doc", "Rust Notes", "rust memory rust safety")).unwrap();
+        appended.append_content("doc", "memory rust borrow").unwrap();
+
+        let fresh = FerrumSearch::new();
+        fresh
+            .add_document(make_doc("doc", "Rust Notes", "rust memory rust safety memory rust borrow"))
+            .unwrap();
+
+        assert_eq!(*appended.word_frequencies.read().unwrap(), *fresh.word_frequencies.read().unwrap());
+        assert_eq!(*appended.term_positions.read().unwrap(), *fresh.term_positions.read().unwrap());
+        assert_eq!(*appended.document_lengths.read().unwrap(), *fresh.document_lengths.read().unwrap());
+        assert_eq!(appended.append_content("missing", "text"), Err(FerrumError::NotFound("missing".to_string())));
+
+        // Appended tokens beyond the cap are dropped, as fresh indexing would
+        appended.set_max_indexed_tokens(Some(10));
+        fresh.set_max_indexed_tokens(Some(10));
+        appended.append_content("doc", "zig memory safety").unwrap();
+        fresh
+            .add_document(make_doc("doc", "Rust Notes", "rust memory rust safety memory rust borrow zig memory safety"))
+            .unwrap();
+        assert_eq!(*appended.word_frequencies.read().unwrap(), *fresh.word_frequencies.read().unwrap());
+        assert_eq!(*appended.term_positions.read().unwrap(), *fresh.term_positions.read().unwrap());
+        assert_eq!(*appended.document_lengths.read().unwrap(), *fresh.document_lengths.read().unwrap());
+    }
+
+    #[test]
+    fn test_structured_clause_words_are_not_operators() {
+        let engine = FerrumSearch::new();
+        engine.add_document(make_doc("async", "Rust", "rust async runtime")).unwrap();
+        engine.add_document(make_doc("legacy", "Rust", "rust legacy code")).unwrap();
+        add_fillers(&engine, 4);
+
+        let structured = StructuredQuery {
+            bool_query: BoolQuery {
+                must: vec!["-rust".to_string()],
+                should: vec!["-async".to_string()],
+                must_not: vec!["+legacy".to_string()],
+            },
+            ..Default::default()
+        };
+        let ids: Vec<String> = engine
+            .search_structured(structured)
+            .unwrap()
+            .results
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, vec!["async"]);
+    }
+
+    #[test]
+    fn test_cache_hits_log_their_own_latency() {
+        let engine = FerrumSearch::new();
+        engine.set_query_cache(10);
+        engine.set_query_log_capacity(10);
+        engine.add_document(make_doc("1", "Rust", "ownership")).unwrap();
+
+        let query = SearchQuery { query: "rust".to_string(), ..Default::default() };
+        let miss = engine.search(query.clone()).unwrap();
+        // Mark the cached copy with a latency no lookup could take
+        let cached_time_ms = 1_000_000;
+        for cached in engine.query_cache.write().unwrap().as_mut().unwrap().entries.values_mut() {
+            cached.query_time_ms = cached_time_ms;
+        }
+        let hit = engine.search(query).unwrap();
+        assert_ne!(hit.query_time_ms, cached_time_ms);
+
+        let latencies: Vec<u64> = engine.recent_queries(2).iter().map(|r| r.latency_ms).collect();
+        assert_eq!(latencies, vec![hit.query_time_ms, miss.query_time_ms]);
+        assert_eq!(engine.query_metrics().total_time_ms, miss.query_time_ms + hit.query_time_ms);
+    }
+}